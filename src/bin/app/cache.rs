@@ -0,0 +1,87 @@
+// src/cache.rs
+
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use vb::embed_cache::QueryCachedEmbedder;
+use vb::lru::{CacheStats, LruCache};
+use vb::store::SearchOptions;
+
+use crate::types::SearchResponse;
+
+/// Identifies one `/api/search` request.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SearchKey {
+    document_id: String,
+    query: String,
+    limit: usize,
+    offset: usize,
+    // Floats by bit pattern, so the key can be hashed
+    min_score: Option<u32>,
+    semantic_weight: u32,
+    parents: bool,
+    rerank_candidates: Option<usize>,
+}
+
+impl SearchKey {
+    pub fn new(
+        document_id: &str,
+        query: &str,
+        options: &SearchOptions,
+        rerank_candidates: Option<usize>,
+    ) -> Self {
+        Self {
+            document_id: document_id.to_string(),
+            query: query.split_whitespace().collect::<Vec<_>>().join(" "),
+            limit: options.limit,
+            offset: options.offset,
+            min_score: options.min_score.map(f32::to_bits),
+            semantic_weight: options.semantic_weight.to_bits(),
+            parents: options.parents,
+            rerank_candidates,
+        }
+    }
+}
+
+/// Recent query embeddings and finished search responses.
+pub struct SearchCache {
+    embeddings: Arc<QueryCachedEmbedder>,
+    results: Mutex<LruCache<SearchKey, SearchResponse>>,
+}
+
+#[derive(Serialize)]
+pub struct SearchCacheStats {
+    pub query_embeddings: CacheStats,
+    pub search_results: CacheStats,
+}
+
+impl SearchCache {
+    pub fn new(embeddings: Arc<QueryCachedEmbedder>, results: usize) -> Self {
+        Self {
+            embeddings,
+            results: Mutex::new(LruCache::new(results)),
+        }
+    }
+
+    pub fn get(&self, key: &SearchKey) -> Option<SearchResponse> {
+        self.results.lock().unwrap().get(key)
+    }
+
+    pub fn insert(&self, key: SearchKey, response: SearchResponse) {
+        self.results.lock().unwrap().insert(key, response);
+    }
+
+    /// Drops cached responses for a document whose chunks changed.
+    pub fn invalidate(&self, document_id: &str) {
+        self.results
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.document_id != document_id);
+    }
+
+    pub fn stats(&self) -> SearchCacheStats {
+        SearchCacheStats {
+            query_embeddings: self.embeddings.stats(),
+            search_results: self.results.lock().unwrap().stats(),
+        }
+    }
+}
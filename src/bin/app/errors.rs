@@ -2,17 +2,33 @@
 
 use axum::{http::StatusCode, response::IntoResponse};
 
-pub struct AppError(anyhow::Error);
+pub struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl AppError {
+    /// An error caused by the request rather than the server.
+    pub fn bad_request(error: impl Into<anyhow::Error>) -> Self {
+        AppError {
+            status: StatusCode::BAD_REQUEST,
+            error: error.into(),
+        }
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+        (self.status, self.error.to_string()).into_response()
     }
 }
 
 // This lets you use ? on anything that converts into anyhow::Error
 impl<E: Into<anyhow::Error>> From<E> for AppError {
     fn from(e: E) -> Self {
-        AppError(e.into())
+        AppError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error: e.into(),
+        }
     }
-}
\ No newline at end of file
+}
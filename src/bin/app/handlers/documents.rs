@@ -0,0 +1,110 @@
+// src/handlers/documents.rs
+
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use vb::registry::DocumentStatus;
+use vb::store;
+
+use crate::errors::AppError;
+use crate::handlers::upload::start_ingest;
+use crate::COLLECTION;
+use crate::types::{AppState, DocumentInfo, UploadResponse};
+
+fn not_found(id: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("No document with id: {}", id)).into_response()
+}
+
+pub async fn list_documents(State(state): State<AppState>) -> Json<Vec<DocumentInfo>> {
+    let registry = state.registry.read().await;
+    Json(registry.list().into_iter().map(DocumentInfo::from).collect())
+}
+
+pub async fn get_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.registry.read().await.get(&id) {
+        Some(record) => Json(DocumentInfo::from(record)).into_response(),
+        None => not_found(&id),
+    }
+}
+
+/// The chunks the filter dropped during the document's last ingest.
+pub async fn get_filter_report(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    match state.registry.read().await.filter_report(&id)? {
+        Some(report) => Ok(Json(report).into_response()),
+        None => Ok(not_found(&id)),
+    }
+}
+
+pub async fn delete_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    // Held until the record is gone, so no re-index can start in between
+    let mut registry = state.registry.write().await;
+    let Some(record) = registry.get(&id).cloned() else {
+        return Ok(not_found(&id));
+    };
+    if record.status == DocumentStatus::Processing {
+        return Ok((StatusCode::CONFLICT, "Document is still being indexed").into_response());
+    }
+
+    if let Some(unique_filename) = &record.unique_filename {
+        store::delete_document(state.store.as_ref(), COLLECTION, unique_filename).await?;
+        state.keywords.remove_document(unique_filename).await?;
+    }
+
+    registry.remove(&id)?;
+    drop(registry);
+    state.id_map.write().await.remove(&id);
+    state.jobs.write().await.remove(&id);
+    state.search_cache.invalidate(&id);
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn reindex_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    // Check and claim the document at once, so concurrent requests cannot
+    // both start a re-index or delete it meanwhile
+    let record = {
+        let mut registry = state.registry.write().await;
+        let Some(record) = registry.get(&id).cloned() else {
+            return Ok(not_found(&id));
+        };
+        if record.status == DocumentStatus::Processing {
+            return Ok((StatusCode::CONFLICT, "Document is already being indexed").into_response());
+        }
+        registry.update(&id, |record| {
+            record.status = DocumentStatus::Processing;
+            record.error = None;
+        })?;
+        record
+    };
+
+    let data = match tokio::fs::read(&record.pdf_path).await {
+        Ok(data) => data,
+        Err(e) => {
+            state.registry.write().await.update(&id, |current| {
+                current.status = record.status;
+                current.error = record.error.clone();
+            })?;
+            return Err(e.into());
+        }
+    };
+    start_ingest(state.clone(), id.clone(), record.filename, record.chunking, data).await;
+
+    // Same shape as an upload, so clients can follow /api/jobs/{id}
+    Ok((StatusCode::ACCEPTED, Json(UploadResponse { id })).into_response())
+}
@@ -0,0 +1,33 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::StreamExt;
+
+use crate::jobs::{job_handle, job_updates};
+use crate::types::AppState;
+
+// handlers/jobs.rs
+pub async fn get_job(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match job_handle(&state.jobs, &id).await {
+        Some(job) => Json(job.borrow().clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No job for id: {}", id)).into_response(),
+    }
+}
+
+/// Streams the job as a `progress` event on every change, starting with its
+/// current state. The stream ends after the job reaches `done` or `failed`.
+pub async fn job_events(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some(job) = job_handle(&state.jobs, &id).await else {
+        return (StatusCode::NOT_FOUND, format!("No job for id: {}", id)).into_response();
+    };
+
+    let events = job_updates(job.subscribe())
+        .map(|job| Event::default().event("progress").json_data(&job));
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
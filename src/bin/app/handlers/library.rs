@@ -0,0 +1,175 @@
+// src/handlers/library.rs
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use std::collections::HashMap;
+use vb::registry::{DocumentRecord, DocumentStatus};
+use vb::store;
+
+use crate::errors::AppError;
+use crate::handlers::split_list;
+use crate::COLLECTION;
+use crate::types::{AppState, LibraryDocumentHits, LibraryHit, LibrarySearchQuery};
+
+const DEFAULT_DOCUMENTS: usize = 10;
+const MAX_DOCUMENTS: usize = 50;
+const DEFAULT_HITS_PER_DOCUMENT: usize = 3;
+const MAX_HITS_PER_DOCUMENT: usize = 10;
+const SNIPPET_CHARS: usize = 240;
+
+/// Searches every searchable document (or the selected ids / tags) and returns the
+/// best documents first, each with its best matching chunks.
+pub async fn search_library(
+    State(state): State<AppState>,
+    Query(params): Query<LibrarySearchQuery>,
+) -> Result<Json<Vec<LibraryDocumentHits>>, AppError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    let ids = params.ids.as_deref().map(split_list);
+    let tags = params.tags.as_deref().map(split_list);
+    let documents = select_documents(
+        state.registry.read().await.list(),
+        ids.as_deref(),
+        tags.as_deref(),
+    );
+
+    // An empty filter would match everything, including orphaned chunks
+    if documents.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    let filenames: Vec<String> = documents.keys().cloned().collect();
+    let groups = store::run_library_query(
+        state.store.as_ref(),
+        &state.embedder,
+        COLLECTION,
+        &filenames,
+        query,
+        params.limit.unwrap_or(DEFAULT_DOCUMENTS).min(MAX_DOCUMENTS),
+        params
+            .per_document
+            .unwrap_or(DEFAULT_HITS_PER_DOCUMENT)
+            .min(MAX_HITS_PER_DOCUMENT),
+    )
+    .await?;
+
+    let results = groups
+        .into_iter()
+        .filter_map(|group| {
+            let (document_id, filename) = documents.get(&group.filename)?.clone();
+            let score = group.hits.first().map_or(0.0, |hit| hit.score);
+            let hits = group
+                .hits
+                .into_iter()
+                .map(|hit| LibraryHit {
+                    page: hit.payload.page,
+                    score: hit.score,
+                    snippet: snippet(&hit.payload.text),
+                })
+                .collect();
+            Some(LibraryDocumentHits {
+                document_id,
+                filename,
+                score,
+                hits,
+            })
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+/// Searchable documents with one of the `ids` and one of the `tags`, either
+/// filter matching everything when absent, keyed by unique filename and
+/// mapped to (document id, original filename). Documents being re-indexed
+/// are searched through their previous index, as in `/api/search`.
+fn select_documents(
+    records: Vec<&DocumentRecord>,
+    ids: Option<&[String]>,
+    tags: Option<&[String]>,
+) -> HashMap<String, (String, String)> {
+    records
+        .into_iter()
+        .filter(|record| {
+            matches!(record.status, DocumentStatus::Ready | DocumentStatus::Processing)
+        })
+        .filter(|record| ids.is_none_or(|ids| ids.contains(&record.id)))
+        .filter(|record| tags.is_none_or(|tags| record.tags.iter().any(|tag| tags.contains(tag))))
+        .filter_map(|record| {
+            let unique_filename = record.unique_filename.clone()?;
+            Some((unique_filename, (record.id.clone(), record.filename.clone())))
+        })
+        .collect()
+}
+
+fn snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= SNIPPET_CHARS {
+        return text;
+    }
+    let cut: String = text.chars().take(SNIPPET_CHARS).collect();
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vb::chunk::ChunkingConfig;
+    use vb::registry::Registry;
+
+    #[test]
+    fn documents_are_selected_by_id_and_tag_once_indexed() {
+        let dir = std::env::temp_dir().join(format!("vb-library-{}", uuid::Uuid::new_v4()));
+        let mut registry = Registry::open(&dir).unwrap();
+        let documents = [
+            ("pump", vec!["manuals"], DocumentStatus::Ready, true),
+            ("valve", vec!["manuals", "legacy"], DocumentStatus::Ready, true),
+            ("invoice", vec![], DocumentStatus::Ready, true),
+            // Re-indexing, so still searched through its previous index
+            ("filter", vec!["parts"], DocumentStatus::Processing, true),
+            ("draft", vec!["manuals"], DocumentStatus::Processing, false),
+            ("scan", vec!["manuals"], DocumentStatus::Failed, false),
+        ];
+        for (id, tags, status, indexed) in documents {
+            let tags = tags.into_iter().map(String::from).collect();
+            registry
+                .create(id, &format!("{}.pdf", id), tags, ChunkingConfig::default(), b"%PDF")
+                .unwrap();
+            registry
+                .update(id, |record| {
+                    record.status = status;
+                    record.unique_filename = indexed.then(|| format!("{}_1.pdf", id));
+                })
+                .unwrap();
+        }
+        // Filters as given in the query string
+        let selected = |ids: Option<&str>, tags: Option<&str>| {
+            let (ids, tags) = (ids.map(split_list), tags.map(split_list));
+            let mut ids: Vec<String> =
+                select_documents(registry.list(), ids.as_deref(), tags.as_deref())
+                    .into_values()
+                    .map(|(id, _)| id)
+                    .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(selected(None, None), ["filter", "invoice", "pump", "valve"]);
+        assert_eq!(selected(Some("pump,draft,scan"), None), ["pump"]);
+        assert_eq!(selected(None, Some("parts")), ["filter"]);
+        assert_eq!(selected(None, Some("manuals")), ["pump", "valve"]);
+        assert_eq!(selected(Some("pump,invoice"), Some("manuals")), ["pump"]);
+        assert!(selected(None, Some("unknown")).is_empty());
+        assert_eq!(
+            select_documents(registry.list(), None, None)["valve_1.pdf"],
+            ("valve".to_string(), "valve.pdf".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
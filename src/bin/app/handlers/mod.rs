@@ -1,5 +1,44 @@
 // src/handlers/mod.rs
 
+pub mod documents;
+pub mod jobs;
+pub mod library;
 pub mod upload;
 pub mod search;
-pub mod ready;
\ No newline at end of file
+pub mod ready;
+
+/// Parses a comma-separated list such as `a, b,c`, skipping empty entries.
+pub fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// State backed by in-memory stores and a hashing embedder, with the
+/// registry in `dir`.
+#[cfg(test)]
+pub fn test_state(dir: &std::path::Path) -> crate::types::AppState {
+    use std::sync::Arc;
+    use vb::embed::HashingEmbedder;
+    use vb::embed_cache::QueryCachedEmbedder;
+    use vb::extract::{Backend, FallbackExtractor};
+
+    let embedder = Arc::new(QueryCachedEmbedder::new(Arc::new(HashingEmbedder::new(64)), 8));
+    crate::types::AppState {
+        id_map: Default::default(),
+        store: Arc::new(vb::memory_store::MemoryStore::new()),
+        embedder: embedder.clone(),
+        keywords: Arc::new(vb::lexical::LexicalIndex::new()),
+        search_cache: Arc::new(crate::cache::SearchCache::new(embedder, 8)),
+        reranker: None,
+        chunking: vb::chunk::ChunkingConfig::default(),
+        extractor: Arc::new(FallbackExtractor::new(&[Backend::Lopdf])),
+        jobs: Default::default(),
+        registry: Arc::new(tokio::sync::RwLock::new(
+            vb::registry::Registry::open(dir).unwrap(),
+        )),
+    }
+}
@@ -7,6 +7,7 @@ use axum::{
 };
 use std::collections::HashMap;
 use serde_json::json;
+use crate::jobs::JobStage;
 use crate::types::{AppState};
 
 // handlers/ready.rs
@@ -15,7 +16,12 @@ pub async fn is_ready(
     Query(params): Query<HashMap<String, String>>,
 ) -> Response {
     if let Some(id) = params.get("id") {
-        let ready = state.ready_set.read().await.contains(id);
+        let ready = state
+            .jobs
+            .read()
+            .await
+            .get(id)
+            .is_some_and(|job| job.borrow().stage == JobStage::Done);
         Json(json!({ "ready": ready })).into_response()
     } else {
         (StatusCode::BAD_REQUEST, "Missing id").into_response()
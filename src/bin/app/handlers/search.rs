@@ -5,20 +5,86 @@ use axum::{
     extract::{Query, State},
     Json,
 };
-use qdrant_client::Qdrant;
-use qdrant_client::qdrant::value::Kind;
-use vb::{fuzzy, qdrant};
+use pdfium_render::prelude::PdfDocument;
+use vb::chunk::PageSpan;
+use vb::fuzzy;
+use vb::lexical::FusedPoint;
+use vb::rerank;
+use vb::store::{self, ChunkPayload, SearchOptions};
 
+use crate::cache::{SearchCacheStats, SearchKey};
 use crate::errors::AppError;
 use crate::pdf::{expand_ligatures, extract_char_bboxes, get_pdfium, snap_to_sentence_boundaries};
-use crate::types::{AppState, PageHighlight, SearchResult, SearchWithBboxQuery};
+use crate::COLLECTION;
+use crate::types::{
+    AppState, CharBbox, PageHighlight, SearchHit, SearchResponse, SearchWithBboxQuery,
+    SEARCH_RESPONSE_VERSION,
+};
+
+/// Upper bound on `limit`, since hits may have to be located in the PDF.
+const MAX_LIMIT: usize = 50;
+
+/// Upper bound on `offset`; deeper pages would make the store rank and sort
+/// every hit before them.
+const MAX_OFFSET: usize = 1000;
+
+/// Candidates passed to the reranker when the request does not say.
+const DEFAULT_RERANK_CANDIDATES: usize = 20;
+const MAX_RERANK_CANDIDATES: usize = 100;
 
 pub async fn search_with_bboxes(
     State(state): State<AppState>,
     Query(params): Query<SearchWithBboxQuery>,
-) -> Result<Json<Vec<PageHighlight>>, AppError> {
+) -> Result<Json<SearchResponse>, AppError> {
+    let defaults = SearchOptions::default();
+    if params.offset.is_some_and(|offset| offset > MAX_OFFSET) {
+        return Err(AppError::bad_request(anyhow::anyhow!(
+            "offset must be at most {}",
+            MAX_OFFSET
+        )));
+    }
+    let options = SearchOptions {
+        limit: params.limit.unwrap_or(defaults.limit).min(MAX_LIMIT),
+        offset: params.offset.unwrap_or(defaults.offset),
+        min_score: params.min_score,
+        semantic_weight: params
+            .semantic_weight
+            .unwrap_or(defaults.semantic_weight)
+            .clamp(0.0, 1.0),
+        parents: params.parents.unwrap_or(false),
+    };
+    let rerank_candidates = if params.rerank.unwrap_or(false) {
+        if state.reranker.is_none() {
+            return Err(AppError::bad_request(anyhow::anyhow!(
+                "Reranking is not enabled on this server; start it with --reranker or omit rerank=true"
+            )));
+        }
+        let candidates = params
+            .rerank_candidates
+            .unwrap_or(DEFAULT_RERANK_CANDIDATES)
+            .min(MAX_RERANK_CANDIDATES);
+        // Every returned hit must have been reranked
+        Some(candidates.max(options.offset + options.limit))
+    } else {
+        None
+    };
+    let mut response = SearchResponse {
+        version: SEARCH_RESPONSE_VERSION,
+        query: params.q.clone(),
+        limit: options.limit,
+        offset: options.offset,
+        semantic_weight: options.semantic_weight,
+        reranked: rerank_candidates.is_some(),
+        hits: vec![],
+    };
+
     if params.q.is_empty() {
-        return Ok(Json(vec![]));
+        return Ok(Json(response));
+    }
+
+    let key = SearchKey::new(&params.id, &params.q, &options, rerank_candidates);
+    if let Some(cached) = state.search_cache.get(&key) {
+        return Ok(Json(cached));
     }
 
     // --- Resolve file name ---
@@ -31,29 +97,43 @@ pub async fn search_with_bboxes(
     };
 
     // --- Run search API ---
-    let search_results = match run_search_api(&state.qdrant, &file_name, &params.q).await {
+    let search_results = match run_search_api(&state, &file_name, &params.q, &options, rerank_candidates).await {
         Ok(results) => results,
         Err(e) => {
-            eprintln!("Error querying Qdrant for file '{}', query '{}': {:?}", file_name, params.q, e);
-            return Err(AppError::from(anyhow::anyhow!("Error querying Qdrant for file '{}', query '{}': {:?}", file_name, params.q, e)))
+            eprintln!("Error querying vector store for file '{}', query '{}': {:?}", file_name, params.q, e);
+            return Err(AppError::from(anyhow::anyhow!("Error querying vector store for file '{}', query '{}': {:?}", file_name, params.q, e)))
         }
     };
 
     if search_results.is_empty() {
-        return Ok(Json(vec![]));
+        state.search_cache.insert(key, response.clone());
+        return Ok(Json(response));
     }
 
-    // --- Get PDF bytes ---
-    let bytes = match get_pdf_bytes(&state, &params.id).await {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("Error getting PDF bytes for id {}: {:?}", params.id, e);
-            return Err(AppError::from(anyhow::anyhow!("Error getting PDF bytes for id {}: {:?}", params.id, e)))
+    // --- Get PDF bytes, only for chunks ingested without highlights ---
+    let needs_pdf = search_results
+        .iter()
+        .any(|(result, _)| result.payload.page_spans().iter().any(|span| span.rects.is_none()));
+    let bytes = if needs_pdf {
+        match get_pdf_bytes(&state, &params.id).await {
+            Ok(b) => Some(b),
+            Err(e) => {
+                eprintln!("Error getting PDF bytes for id {}: {:?}", params.id, e);
+                return Err(AppError::from(anyhow::anyhow!("Error getting PDF bytes for id {}: {:?}", params.id, e)))
+            }
         }
+    } else {
+        None
     };
 
     // --- Compute highlights ---
-    let highlights = match compute_highlights(&bytes, &search_results) {
+    // Locating chunks in the PDF is CPU-bound; keep it off the async runtime
+    let offset = options.offset;
+    let computed = tokio::task::spawn_blocking(move || {
+        compute_hits(bytes.as_deref(), search_results, offset)
+    })
+    .await?;
+    response.hits = match computed {
         Ok(h) => h,
         Err(e) => {
             eprintln!("Error computing highlights for file '{}': {:?}", file_name, e);
@@ -61,7 +141,12 @@ pub async fn search_with_bboxes(
         }
     };
 
-    Ok(Json(highlights))
+    state.search_cache.insert(key, response.clone());
+    Ok(Json(response))
+}
+
+pub async fn search_cache_stats(State(state): State<AppState>) -> Json<SearchCacheStats> {
+    Json(state.search_cache.stats())
 }
 
 async fn resolve_file_name(state: &AppState, id: &str) -> Result<String> {
@@ -74,111 +159,271 @@ async fn resolve_file_name(state: &AppState, id: &str) -> Result<String> {
         .ok_or_else(|| anyhow::anyhow!("No file found for id: {}", id))
 }
 
+/// Reads the registry's copy of the PDF. Only chunks ingested without
+/// highlights need it, so it is not kept in memory.
 async fn get_pdf_bytes(state: &AppState, id: &str) -> Result<Vec<u8>> {
-    state
-        .bytes_map
+    let pdf_path = state
+        .registry
         .read()
         .await
         .get(id)
-        .cloned()
-        .ok_or_else(|| anyhow::anyhow!("No bytes found for id: {}", id))
+        .map(|record| record.pdf_path.clone())
+        .ok_or_else(|| anyhow::anyhow!("No bytes found for id: {}", id))?;
+    Ok(tokio::fs::read(&pdf_path).await?)
 }
 
-async fn run_search_api(client: &Qdrant, file_name: &str, query: &str) -> Result<Vec<SearchResult>> {
+/// Runs the hybrid query, optionally reranking the top `rerank_candidates`
+/// fused hits. Returns each hit with its reranker score, if any.
+async fn run_search_api(
+    state: &AppState,
+    file_name: &str,
+    query: &str,
+    options: &SearchOptions,
+    rerank_candidates: Option<usize>,
+) -> Result<Vec<(FusedPoint, Option<f32>)>> {
     let query = query.trim();
     if query.is_empty() {
         return Ok(vec![]);
     }
 
-    let resp = qdrant::run_query(client, "embedded_pdfs", file_name, query)
+    let (Some(reranker), Some(candidates)) = (&state.reranker, rerank_candidates) else {
+        let hits = hybrid_query(state, file_name, query, options).await?;
+        return Ok(hits.into_iter().map(|hit| (hit, None)).collect());
+    };
+
+    let candidate_options = SearchOptions {
+        limit: candidates,
+        offset: 0,
+        ..*options
+    };
+    let hits = hybrid_query(state, file_name, query, &candidate_options).await?;
+    let reranked = rerank::rerank(reranker, query, hits, |hit| hit.payload.text.as_str())
         .await
-        .map_err(|e| anyhow::anyhow!("Qdrant query failed: {:?}", e))?;
+        .map_err(|e| anyhow::anyhow!("Reranking failed: {:?}", e))?;
 
-    let results = resp
-        .result
+    Ok(reranked
         .into_iter()
-        .filter_map(|point| {
-            let text = point.payload.get("text")?.as_str()?.to_string();
-            let page = match &point.payload.get("page")?.kind {
-                Some(Kind::DoubleValue(d)) => *d as i64,
-                Some(Kind::IntegerValue(i)) => *i,
-                Some(Kind::StringValue(s)) => s.parse().unwrap_or(1),
-                _ => 1,
+        .skip(options.offset)
+        .take(options.limit)
+        .map(|(hit, score)| (hit, Some(score)))
+        .collect())
+}
+
+async fn hybrid_query(
+    state: &AppState,
+    file_name: &str,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<FusedPoint>> {
+    store::run_hybrid_query(
+        state.store.as_ref(),
+        &state.embedder,
+        &state.keywords,
+        COLLECTION,
+        file_name,
+        query,
+        options,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("Vector store query failed: {:?}", e))
+}
+
+fn compute_hits(bytes: Option<&[u8]>, search_results: Vec<(FusedPoint, Option<f32>)>, offset: usize) -> Result<Vec<SearchHit>> {
+    let doc = match bytes {
+        Some(bytes) => Some(
+            get_pdfium()?
+                .load_pdf_from_byte_slice(bytes, None)
+                .map_err(|e| anyhow::anyhow!("PDFium load failed: {:?}", e))?,
+        ),
+        None => None,
+    };
+
+    let hits = search_results
+        .into_iter()
+        .enumerate()
+        .map(|(i, (result, rerank_score))| {
+            let spans = result.payload.page_spans();
+            let end_page = spans.last().map_or(result.payload.page, |span| span.page);
+            let (fuzzy_score, snippet, highlights) = match (precomputed_highlights(&spans), &doc) {
+                (Some(highlights), _) => (None, result.payload.text.clone(), highlights),
+                (None, Some(doc)) => locate_spans(doc, &result.payload),
+                (None, None) => (None, result.payload.text.clone(), vec![]),
             };
-            Some(SearchResult { page, text })
+
+            SearchHit {
+                rank: offset + i + 1,
+                chunk_id: result.id,
+                page: result.payload.page as usize,
+                end_page: end_page as usize,
+                score: result.score,
+                vector_score: result.vector_score,
+                lexical_score: result.lexical_score,
+                rerank_score,
+                fuzzy_score,
+                snippet,
+                highlights,
+                parent: result.payload.parent,
+                duplicates: result.payload.duplicates,
+            }
         })
         .collect();
 
-    Ok(results)
+    Ok(hits)
 }
 
-fn compute_highlights(bytes: &[u8], search_results: &[SearchResult]) -> Result<Vec<PageHighlight>> {
-    let pdfium = get_pdfium();
-    let doc = pdfium.load_pdf_from_byte_slice(bytes, None)
-        .map_err(|e| anyhow::anyhow!("PDFium load failed: {:?}", e))?;
-    let mut highlights: Vec<PageHighlight> = Vec::new();
+/// The highlights stored with the chunk at ingest, if all its spans have them.
+fn precomputed_highlights(spans: &[PageSpan]) -> Option<Vec<PageHighlight>> {
+    spans
+        .iter()
+        .map(|span| {
+            Some(PageHighlight {
+                page: span.page as usize,
+                rects: span.rects.clone()?,
+            })
+        })
+        .collect()
+}
 
-    for search_result in search_results {
-        let page_idx = (search_result.page - 1) as u16;
+/// Locates the part of the chunk on each of its pages. Returns the weakest
+/// match score, the matched page texts and the highlights, or the chunk text
+/// when no part was found.
+fn locate_spans(doc: &PdfDocument, payload: &ChunkPayload) -> (Option<f32>, String, Vec<PageHighlight>) {
+    let chars: Vec<char> = payload.text.chars().collect();
+    let mut fuzzy_score: Option<f32> = None;
+    let mut snippets = Vec::new();
+    let mut highlights = Vec::new();
 
-        let page = match doc.pages().get(page_idx) {
-            Ok(p) => p,
-            Err(_) => {
-                eprintln!("Invalid page index {} for PDF", page_idx);
-                continue;
-            }
-        };
-        let text_page = match page.text() {
-            Ok(t) => t,
-            Err(_) => {
-                eprintln!("Failed to get text for page {}", page_idx + 1);
-                continue;
-            }
-        };
+    for span in payload.page_spans() {
+        let end = span.end.min(chars.len());
+        let text: String = chars[span.start.min(end)..end].iter().collect();
+        // Fuzzy search is only needed when the span's position is unknown
+        let exact = span.pdf_chars.and_then(|pdf_chars| {
+            let rects = pdf_char_rects(doc, span.page, pdf_chars.collect())?;
+            Some((1.0, text.clone(), rects))
+        });
+        if let Some((score, snippet, rects)) = exact.or_else(|| locate_chunk(doc, span.page, &text)) {
+            fuzzy_score = Some(fuzzy_score.map_or(score, |s| s.min(score)));
+            snippets.push(snippet);
+            highlights.push(PageHighlight {
+                page: span.page as usize,
+                rects,
+            });
+        }
+    }
 
-        let needle_chars: Vec<char> = search_result.text.to_lowercase().chars().collect();
+    if snippets.is_empty() {
+        return (None, payload.text.clone(), highlights);
+    }
+    (fuzzy_score, snippets.join(" "), highlights)
+}
 
-        let char_entries: Vec<(usize, char)> = text_page
-            .chars()
-            .iter()
-            .enumerate()
-            .flat_map(|(pdf_idx, c)| {
-                c.unicode_char()
-                    .map(|ch| expand_ligatures(pdf_idx, ch))
-                    .unwrap_or_default()
-            })
-            .collect();
+/// Highlight rectangles of the given chars of a page.
+fn pdf_char_rects(doc: &PdfDocument, page_number: u16, pdf_chars: Vec<usize>) -> Option<Vec<CharBbox>> {
+    let page = doc.pages().get(page_number.checked_sub(1)?).ok()?;
+    let text_page = page.text().ok()?;
+    match extract_char_bboxes(&text_page, &pdf_chars) {
+        Ok(rects) => Some(rects),
+        Err(e) => {
+            eprintln!("Failed to extract bounding boxes for page {}: {:?}", page_number, e);
+            None
+        }
+    }
+}
 
-        let fuzzy_matches = fuzzy::fuzzy_search(&char_entries, &needle_chars, 0.85);
+/// Finds the text's best fuzzy match on its page, returning the match score,
+/// the matched page text and its highlight rectangles.
+fn locate_chunk(doc: &PdfDocument, page_number: u16, text: &str) -> Option<(f32, String, Vec<CharBbox>)> {
+    let page_idx = page_number.checked_sub(1)?;
 
-        let snapped_matches: Vec<(usize, usize, f32)> = fuzzy_matches
-            .into_iter()
-            .map(|(start, end, score)| {
-                let (new_start, new_end) = snap_to_sentence_boundaries(&char_entries, start, end);
-                (new_start, new_end, score)
-            })
-            .collect();
-
-        for (entry_start, entry_end, _score) in snapped_matches {
-
-            let pdf_char_indices: Vec<usize> = char_entries[entry_start..entry_end]
-                .iter()
-                .map(|(pdf_idx, _)| *pdf_idx)
-                .collect();
-
-            match extract_char_bboxes(&text_page, &pdf_char_indices) {
-                Ok(rects) if !rects.is_empty() => highlights.push(PageHighlight {
-                    page: search_result.page as usize,
-                    rects,
-                }),
-                Ok(_) => continue,
-                Err(e) => eprintln!(
-                    "Failed to extract bounding boxes for page {}: {:?}",
-                    search_result.page, e
-                ),
-            }
+    let page = match doc.pages().get(page_idx) {
+        Ok(p) => p,
+        Err(_) => {
+            eprintln!("Invalid page index {} for PDF", page_idx);
+            return None;
+        }
+    };
+    let text_page = match page.text() {
+        Ok(t) => t,
+        Err(_) => {
+            eprintln!("Failed to get text for page {}", page_number);
+            return None;
+        }
+    };
+
+    let needle_chars: Vec<char> = text.to_lowercase().chars().collect();
+
+    let char_entries: Vec<(usize, char)> = text_page
+        .chars()
+        .iter()
+        .enumerate()
+        .flat_map(|(pdf_idx, c)| {
+            c.unicode_char()
+                .map(|ch| expand_ligatures(pdf_idx, ch))
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let (start, end, score) = fuzzy::fuzzy_search(&char_entries, &needle_chars, 0.85)
+        .into_iter()
+        .max_by(|a, b| a.2.total_cmp(&b.2))?;
+    let (start, end) = snap_to_sentence_boundaries(&char_entries, start, end);
+
+    let matched = &char_entries[start..end];
+    let snippet: String = matched.iter().map(|(_, ch)| *ch).collect();
+    let pdf_char_indices: Vec<usize> = matched.iter().map(|(pdf_idx, _)| *pdf_idx).collect();
+
+    match extract_char_bboxes(&text_page, &pdf_char_indices) {
+        Ok(rects) => Some((score, snippet, rects)),
+        Err(e) => {
+            eprintln!("Failed to extract bounding boxes for page {}: {:?}", page_number, e);
+            Some((score, snippet, vec![]))
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    #[tokio::test]
+    async fn offsets_past_the_maximum_are_bad_requests() {
+        let dir = std::env::temp_dir().join(format!("vb-search-{}", uuid::Uuid::new_v4()));
+        let params = |offset| SearchWithBboxQuery {
+            id: "doc".to_string(),
+            q: "filter".to_string(),
+            limit: Some(5),
+            offset: Some(offset),
+            min_score: None,
+            semantic_weight: None,
+            rerank: None,
+            rerank_candidates: None,
+            parents: Some(true),
+        };
+
+        let state = crate::handlers::test_state(&dir);
+        let error = search_with_bboxes(State(state.clone()), Query(params(usize::MAX)))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
 
-    Ok(highlights)
-}
\ No newline at end of file
+        let metadata = store::CollectionMetadata::default();
+        store::ensure_collection(state.store.as_ref(), COLLECTION, 64, &metadata)
+            .await
+            .unwrap();
+        state
+            .id_map
+            .write()
+            .await
+            .insert("doc".to_string(), "manual.pdf_1".to_string());
+        let response = search_with_bboxes(State(state), Query(params(MAX_OFFSET)))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(response.offset, MAX_OFFSET);
+        assert!(response.hits.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,86 +2,384 @@
 
 use anyhow::Result;
 use axum::{
-    body::Bytes,
-    extract::{Multipart, State},
+    extract::{multipart::Field, Multipart, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
-use std::sync::Arc;
+use std::str::FromStr;
 use std::time::Instant;
-use qdrant_client::Qdrant;
+use tokio::sync::watch;
 use uuid::Uuid;
-use vb::{chunk, embed, qdrant};
+use vb::progress::Progress;
+use vb::registry::DocumentStatus;
+use vb::store;
+use vb::chunk::{ChunkingConfig, FilterReport};
+use vb::{chunk, embed, pdfium};
 
 use crate::errors::AppError;
+use crate::handlers::split_list;
+use crate::COLLECTION;
+use crate::jobs::{Job, JobHandle, JobStage};
 use crate::types::{AppState, UploadResponse};
 
 pub async fn handle_upload(
     State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, AppError> {
+    let mut pdf = None;
+    let mut tags = Vec::new();
+    let mut method = None;
+    let mut size = None;
+    let mut overlap = None;
+    let mut cleaning = None;
+    let mut cross_page = None;
+    let mut filter = None;
+    let mut parent_size = None;
+    let mut dedup = None;
+
     while let Some(field) = multipart.next_field().await? {
-        if field.name() != Some("pdf") {
-            continue;
+        let name = field.name().map(str::to_string);
+        match name.as_deref() {
+            Some("pdf") => {
+                let filename = field
+                    .file_name()
+                    .ok_or_else(|| AppError::bad_request(anyhow::anyhow!("Missing filename")))?
+                    .to_string();
+                pdf = Some((filename, field.bytes().await?));
+            }
+            Some("tags") => tags = split_list(&field.text().await?),
+            Some("chunking") => method = Some(parse_field(field).await?),
+            Some("chunk_size") => size = Some(parse_field(field).await?),
+            Some("chunk_overlap") => overlap = Some(parse_field(field).await?),
+            Some("cleaning") => cleaning = Some(parse_field(field).await?),
+            Some("cross_page") => cross_page = Some(parse_field(field).await?),
+            Some("filter") => filter = Some(parse_field(field).await?),
+            Some("parent_size") => parent_size = Some(parse_field(field).await?),
+            Some("dedup") => dedup = Some(parse_field(field).await?),
+            _ => continue,
         }
+    }
 
-        let filename = field.file_name().ok_or_else(|| anyhow::anyhow!("Missing filename"))?.to_string();
-        let data = field.bytes().await?;
+    let Some((filename, data)) = pdf else {
+        return Err(AppError::bad_request(anyhow::anyhow!(
+            "No PDF field found in multipart body"
+        )));
+    };
 
-        println!("Received file: {} ({} bytes)", filename, data.len());
+    // A chosen method starts from its own defaults, as sizes differ in unit
+    let defaults = method.map_or(state.chunking, ChunkingConfig::new);
+    let chunking = ChunkingConfig {
+        method: defaults.method,
+        size: size.unwrap_or(defaults.size),
+        overlap: overlap.unwrap_or(defaults.overlap),
+        cleaning: cleaning.unwrap_or(defaults.cleaning),
+        cross_page: cross_page.unwrap_or(state.chunking.cross_page),
+        filter: filter.unwrap_or(state.chunking.filter),
+        parent_size: parent_size.or(state.chunking.parent_size),
+        dedup: dedup.unwrap_or(state.chunking.dedup),
+    };
+    // Reject bad settings now rather than in the background job
+    chunking
+        .strategy(state.embedder.tokenizer())
+        .map_err(AppError::bad_request)?;
+    chunking.parent_strategy().map_err(AppError::bad_request)?;
 
-        let id = Uuid::new_v4().to_string();
+    println!("Received file: {} ({} bytes)", filename, data.len());
 
-        {
-            let mut map = state.id_map.write().await;
-            map.insert(id.clone(), "processing".to_string());
-        }
-        {
-            let mut map = state.bytes_map.write().await;
-            map.insert(id.clone(), data.to_vec());
-        }
+    let id = Uuid::new_v4().to_string();
+
+    state
+        .registry
+        .write()
+        .await
+        .create(&id, &filename, tags, chunking, &data)?;
+
+    {
+        let mut map = state.id_map.write().await;
+        map.insert(id.clone(), "processing".to_string());
+    }
+
+    start_ingest(state.clone(), id.clone(), filename, chunking, data.into()).await;
+
+    Ok((StatusCode::OK, Json(UploadResponse { id })))
+}
 
-        let data_clone = data.to_vec();
-        let filename_clone = filename.clone();
-        let id_clone = id.clone();
-        let id_map_clone = state.id_map.clone();
-        let ready_set_clone = state.ready_set.clone();
-        let qdrant = state.qdrant.clone();
-
-        tokio::spawn(async move {
-            let start = Instant::now();
-            match process_file(&filename_clone, data_clone.into(), qdrant).await {
-                Ok(unique_filename) => {
-                    println!("Processing done: {:?}", start.elapsed());
-                    let mut map = id_map_clone.write().await;
-                    let mut set = ready_set_clone.write().await;
-                    set.insert(id_clone.clone());
-                    map.insert(id_clone, unique_filename);
+/// Parses a settings field, rejecting bad values as a bad request.
+async fn parse_field<T>(field: Field<'_>) -> Result<T, AppError>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    let name = field.name().unwrap_or_default().to_string();
+    let value = field.text().await?;
+    value.trim().parse().map_err(|e: T::Err| {
+        AppError::bad_request(anyhow::anyhow!("Invalid {}: {}", name, e.into()))
+    })
+}
+
+/// Registers a fresh job for the document and runs ingestion in the
+/// background, tracking progress in the job map and the final outcome in the
+/// registry. Chunks from a previous ingestion are only removed once the new
+/// ones are stored, so the document stays searchable while it is re-indexed,
+/// and keeps its previous index if re-indexing fails.
+pub async fn start_ingest(
+    state: AppState,
+    id: String,
+    filename: String,
+    chunking: ChunkingConfig,
+    data: Vec<u8>,
+) {
+    let job = watch::channel(Job::new(&id)).0;
+    state.jobs.write().await.insert(id.clone(), job.clone());
+
+    tokio::spawn(async move {
+        let start = Instant::now();
+        match process_file(&state, &job, &id, &filename, &chunking, data).await {
+            Ok(unique_filename) => {
+                println!("Processing done: {:?}", start.elapsed());
+                let (pages, chunks) = {
+                    let job = job.borrow();
+                    (job.pages, job.chunks_stored)
+                };
+
+                let mut previous = None;
+                let saved = state.registry.write().await.update(&id, |record| {
+                    previous = record.unique_filename.replace(unique_filename.clone());
+                    record.status = DocumentStatus::Ready;
+                    record.error = None;
+                    record.pages = pages;
+                    record.chunks = chunks;
+                });
+                if let Err(e) = saved {
+                    eprintln!("Failed to update registry for {}: {:?}", id, e);
                 }
-                Err(e) => {
-                    eprintln!("Processing failed: {:?}", e);
-                    let mut map = id_map_clone.write().await;
-                    let mut set = ready_set_clone.write().await;
-                    map.insert(id_clone, "failed".to_string());
-                    set.insert("failed".to_string());
+                state.id_map.write().await.insert(id.clone(), unique_filename);
+                state.search_cache.invalidate(&id);
+
+                if let Some(previous) = previous {
+                    if let Err(e) =
+                        store::delete_document(state.store.as_ref(), COLLECTION, &previous).await
+                    {
+                        eprintln!("Failed to remove old chunks of {}: {:?}", id, e);
+                    }
+                    if let Err(e) = state.keywords.remove_document(&previous).await {
+                        eprintln!("Failed to remove old keyword index of {}: {:?}", id, e);
+                    }
+                }
+                job.send_modify(|job| job.stage = JobStage::Done);
+            }
+            Err(e) => {
+                eprintln!("Processing failed: {:?}", e);
+                let saved = state.registry.write().await.update(&id, |record| {
+                    // A failed re-index leaves the previous index searchable
+                    if record.unique_filename.is_some() {
+                        record.status = DocumentStatus::Ready;
+                        record.error =
+                            Some(format!("Re-indexing failed; the previous index is kept: {}", e));
+                    } else {
+                        record.status = DocumentStatus::Failed;
+                        record.error = Some(e.to_string());
+                    }
+                });
+                if let Err(e) = saved {
+                    eprintln!("Failed to update registry for {}: {:?}", id, e);
                 }
+                job.send_modify(|job| {
+                    job.stage = JobStage::Failed;
+                    job.error = Some(e.to_string());
+                });
             }
+        }
+    });
+}
+
+async fn process_file(
+    state: &AppState,
+    job: &JobHandle,
+    id: &str,
+    filename: &str,
+    chunking: &ChunkingConfig,
+    pdf_data: Vec<u8>,
+) -> Result<String> {
+    let on_progress = |progress| job.send_modify(|job| job.apply(progress));
+
+    // Extraction, chunking and highlighting are CPU-bound like inference;
+    // keep them off the async runtime threads too
+    job.send_modify(|job| job.stage = JobStage::Extracting);
+    let extractor = state.extractor.clone();
+    let extract_job = job.clone();
+    let (pages, pdf_data) = tokio::task::spawn_blocking(move || {
+        let pages = extractor.extract(&pdf_data, &|progress| {
+            extract_job.send_modify(|job| job.apply(progress))
         });
+        pages.map(|pages| (pages, pdf_data))
+    })
+    .await??;
+    let page_backends = pages
+        .iter()
+        .filter_map(|page| Some((page.page_num, page.backend?)))
+        .collect();
+    state
+        .registry
+        .write()
+        .await
+        .update(id, |record| record.page_backends = page_backends)?;
 
-        return Ok((StatusCode::OK, Json(UploadResponse { id })));
-    }
+    job.send_modify(|job| job.stage = JobStage::Chunking);
+    let embedder = state.embedder.clone();
+    let chunking = *chunking;
+    let (chunks, report) = tokio::task::spawn_blocking(move || {
+        let (mut chunks, report) = chunk::chunk_pages(&pages, &chunking, embedder.tokenizer())?;
+        // Search serves highlights from the payload instead of opening the PDF
+        pdfium::precompute_highlights(&pdf_data, &mut chunks);
+        anyhow::Ok((chunks, report))
+    })
+    .await??;
+    report_dropped(id, &report);
+    state.registry.write().await.save_filter_report(id, &report)?;
+    on_progress(Progress::ChunksCreated {
+        chunks: chunks.len(),
+    });
 
-    Err(AppError::from(anyhow::anyhow!("No PDF field found in multipart body")))
-}
+    job.send_modify(|job| job.stage = JobStage::Embedding);
+    let embedder = state.embedder.clone();
+    let embed_job = job.clone();
+    let embedded_chunks = tokio::task::spawn_blocking(move || {
+        embed::get_embeddings_with_progress(embedder.as_ref(), chunks, &|progress| {
+            embed_job.send_modify(|job| job.apply(progress))
+        })
+    })
+    .await??;
 
-async fn process_file(filename: &str, pdf_data: Bytes, client: Arc<Qdrant>) -> Result<String> {
-    let chunks = chunk::extract_and_chunk(chunk::PdfSource::Bytes(pdf_data.to_vec()))?;
-    let embedded_chunks = embed::get_embeddings(chunks)?;
-    let unique_filename =
-        qdrant::store_embeddings(&client, "embedded_pdfs", filename, embedded_chunks).await?;
+    job.send_modify(|job| job.stage = JobStage::Storing);
+    let unique_filename = store::store_embeddings_with_progress(
+        state.store.as_ref(),
+        COLLECTION,
+        filename,
+        embedded_chunks,
+        &on_progress,
+    )
+    .await?;
+
+    let indexed = store::index_keywords(
+        state.store.as_ref(),
+        &state.keywords,
+        COLLECTION,
+        &unique_filename,
+    )
+    .await;
+    if let Err(e) = indexed {
+        // Not searchable without its keyword index; drop the new chunks
+        if let Err(e) =
+            store::delete_document(state.store.as_ref(), COLLECTION, &unique_filename).await
+        {
+            eprintln!("Failed to remove chunks of {}: {:?}", unique_filename, e);
+        }
+        return Err(e);
+    }
 
     println!("File processed successfully!");
 
     Ok(unique_filename)
-}
\ No newline at end of file
+}
+
+fn report_dropped(id: &str, report: &FilterReport) {
+    if report.collapsed > 0 {
+        println!("Collapsed {} near-duplicate chunks", report.collapsed);
+    }
+    if report.dropped.is_empty() {
+        return;
+    }
+    let counts: Vec<String> = report
+        .counts()
+        .into_iter()
+        .map(|(rule, count)| format!("{} {}", rule.name(), count))
+        .collect();
+    println!(
+        "Dropped {} chunks ({}), see /api/documents/{}/filter-report",
+        report.dropped.len(),
+        counts.join(", "),
+        id
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_state as state;
+
+    #[tokio::test]
+    async fn unreadable_uploads_move_the_job_and_record_to_failed() {
+        let dir = std::env::temp_dir().join(format!("vb-upload-{}", Uuid::new_v4()));
+        let state = state(&dir);
+        let data = b"not a pdf".to_vec();
+        let chunking = ChunkingConfig::default();
+        state
+            .registry
+            .write()
+            .await
+            .create("doc", "broken.pdf", vec![], chunking, &data)
+            .unwrap();
+
+        start_ingest(state.clone(), "doc".into(), "broken.pdf".into(), chunking, data).await;
+        let mut rx = state.jobs.read().await["doc"].subscribe();
+        let mut stages = vec![rx.borrow_and_update().stage];
+        while !rx.borrow().is_finished() {
+            rx.changed().await.unwrap();
+            stages.push(rx.borrow_and_update().stage);
+        }
+
+        // Updates may coalesce, but never run backwards or past the failure
+        assert!(stages.windows(2).all(|pair| pair[0] as u8 <= pair[1] as u8));
+        assert_eq!(stages.last(), Some(&JobStage::Failed));
+        assert!(!stages.contains(&JobStage::Chunking));
+        assert!(rx.borrow().error.is_some());
+
+        let registry = state.registry.read().await;
+        let record = registry.get("doc").unwrap();
+        assert_eq!(record.status, DocumentStatus::Failed);
+        assert_eq!(record.error, rx.borrow().error);
+        assert_eq!(record.unique_filename, None);
+        drop(registry);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_reindexes_keep_the_previous_index_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("vb-upload-{}", Uuid::new_v4()));
+        let state = state(&dir);
+        let chunking = ChunkingConfig::default();
+        {
+            let mut registry = state.registry.write().await;
+            registry
+                .create("doc", "manual.pdf", vec![], chunking, b"%PDF")
+                .unwrap();
+            registry
+                .update("doc", |record| {
+                    record.status = DocumentStatus::Processing;
+                    record.unique_filename = Some("manual.pdf_old".to_string());
+                })
+                .unwrap();
+        }
+
+        let data = b"not a pdf".to_vec();
+        start_ingest(state.clone(), "doc".into(), "manual.pdf".into(), chunking, data).await;
+        let mut rx = state.jobs.read().await["doc"].subscribe();
+        while !rx.borrow_and_update().is_finished() {
+            rx.changed().await.unwrap();
+        }
+        assert_eq!(rx.borrow().stage, JobStage::Failed);
+
+        let mut registry = state.registry.write().await;
+        let record = registry.get("doc").unwrap();
+        assert_eq!(record.status, DocumentStatus::Ready);
+        assert!(record.error.as_ref().unwrap().contains("previous index is kept"));
+
+        let (id_map, jobs) = crate::restore_state(&mut registry).unwrap();
+        assert_eq!(id_map["doc"], "manual.pdf_old");
+        assert_eq!(jobs["doc"].stage, JobStage::Done);
+        drop(registry);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+// src/jobs.rs
+
+use futures::{Stream, stream};
+use serde::Serialize;
+use tokio::sync::watch;
+use vb::progress::Progress;
+
+use crate::types::IdJobMap;
+
+/// Ingestion stages, in the order a job moves through them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    Queued,
+    Extracting,
+    Chunking,
+    Embedding,
+    Storing,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub stage: JobStage,
+    /// Set once the job has failed.
+    pub error: Option<String>,
+    pub pages: usize,
+    pub pages_extracted: usize,
+    /// Pages re-extracted so far by a fallback backend.
+    pub pages_retried: usize,
+    pub chunks: usize,
+    pub chunks_embedded: usize,
+    pub chunks_stored: usize,
+}
+
+/// Current state of a job; subscribers are woken on every change.
+pub type JobHandle = watch::Sender<Job>;
+
+impl Job {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            stage: JobStage::Queued,
+            error: None,
+            pages: 0,
+            pages_extracted: 0,
+            pages_retried: 0,
+            chunks: 0,
+            chunks_embedded: 0,
+            chunks_stored: 0,
+        }
+    }
+
+    pub fn done(id: &str) -> Self {
+        Self {
+            stage: JobStage::Done,
+            ..Self::new(id)
+        }
+    }
+
+    pub fn failed(id: &str, error: impl Into<String>) -> Self {
+        Self {
+            stage: JobStage::Failed,
+            error: Some(error.into()),
+            ..Self::new(id)
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, JobStage::Done | JobStage::Failed)
+    }
+
+    /// Folds a pipeline progress update into the job counters.
+    pub fn apply(&mut self, progress: Progress) {
+        match progress {
+            Progress::PagesExtracted { done, total } => {
+                self.pages_extracted = done;
+                self.pages = total;
+            }
+            Progress::PagesRetried { done, total } => {
+                self.pages_retried = done;
+                self.pages = total;
+            }
+            Progress::ChunksCreated { chunks } => self.chunks = chunks,
+            Progress::EmbeddingBatch { embedded, .. } => self.chunks_embedded = embedded,
+            Progress::PointsStored { stored, .. } => self.chunks_stored = stored,
+        }
+    }
+}
+
+/// The job's current state, then every change up to and including the one
+/// that finishes it. Changes made between polls are coalesced.
+pub fn job_updates(rx: watch::Receiver<Job>) -> impl Stream<Item = Job> {
+    stream::unfold(Some((rx, true)), |state| async move {
+        let (mut rx, first) = state?;
+        if !first && rx.changed().await.is_err() {
+            return None;
+        }
+
+        let job = rx.borrow_and_update().clone();
+        let next = (!job.is_finished()).then_some((rx, false));
+        Some((job, next))
+    })
+}
+
+pub async fn job_handle(jobs: &IdJobMap, id: &str) -> Option<JobHandle> {
+    jobs.read().await.get(id).cloned()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn progress_updates_counters_until_the_job_finishes() {
+        let mut job = Job::new("doc");
+        assert_eq!(job.stage, JobStage::Queued);
+        assert!(!job.is_finished());
+
+        job.apply(Progress::PagesExtracted { done: 2, total: 5 });
+        job.apply(Progress::PagesRetried { done: 1, total: 5 });
+        job.apply(Progress::ChunksCreated { chunks: 12 });
+        job.apply(Progress::EmbeddingBatch {
+            batch: 1,
+            batches: 2,
+            embedded: 8,
+        });
+        job.apply(Progress::PointsStored { stored: 4, total: 12 });
+        assert_eq!((job.pages, job.pages_extracted, job.pages_retried), (5, 2, 1));
+        assert_eq!((job.chunks, job.chunks_embedded, job.chunks_stored), (12, 8, 4));
+        assert!(!job.is_finished());
+
+        assert!(Job::done("doc").is_finished());
+        let failed = Job::failed("doc", "bad pdf");
+        assert!(failed.is_finished());
+        assert_eq!(failed.error.as_deref(), Some("bad pdf"));
+    }
+
+    #[tokio::test]
+    async fn updates_start_with_the_current_state_and_end_when_finished() {
+        let job = watch::channel(Job::new("doc")).0;
+        job.send_modify(|job| job.stage = JobStage::Extracting);
+        let mut updates = Box::pin(job_updates(job.subscribe()));
+
+        let first = updates.next().await.unwrap();
+        assert_eq!(first.stage, JobStage::Extracting);
+
+        job.send_modify(|job| job.apply(Progress::PagesExtracted { done: 1, total: 3 }));
+        let next = updates.next().await.unwrap();
+        assert_eq!((next.stage, next.pages_extracted), (JobStage::Extracting, 1));
+
+        job.send_modify(|job| job.stage = JobStage::Done);
+        assert_eq!(updates.next().await.unwrap().stage, JobStage::Done);
+        // Ends with the job finished, not when the sender goes away
+        assert!(updates.next().await.is_none());
+        drop(job);
+    }
+}
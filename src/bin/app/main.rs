@@ -1,14 +1,17 @@
 // src/main.rs
 
+mod cache;
 mod errors;
 mod handlers;
+mod jobs;
 mod pdf;
 mod types;
 
-use std::{collections::HashSet, net::SocketAddr};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
 
 use axum::{
     Router,
@@ -17,39 +20,294 @@ use axum::{
     routing::{get, post},
     http::StatusCode,
 };
-use qdrant_client::Qdrant;
-use tokio::sync::RwLock;
+use clap::{Parser, ValueEnum};
+use tokio::sync::{RwLock, watch};
 use tower_http::services::ServeDir;
-use vb::qdrant;
+use vb::chunk::{ChunkFilter, ChunkingConfig, ChunkingMethod, Cleaning};
+use vb::embed::{self, Embedder, EmbedderConfig, EmbeddingModel, PromptedEmbedder, Prompts};
+use vb::extract::{Backend, FallbackExtractor};
+use vb::embed_cache::{CachedEmbedder, EmbeddingCache, QueryCachedEmbedder};
+use vb::lexical::LexicalIndex;
+use vb::memory_store::MemoryStore;
+use vb::qdrant::QdrantStore;
+use vb::registry::{DocumentStatus, Registry};
+use vb::rerank::{FastembedReranker, Reranker, RerankerModel};
+use vb::store::{self, CollectionMetadata, VectorStore};
 
-use types::{AppState, IdToBytesMap, IdToFilenameMap, IdReadyMap};
-use handlers::{upload::handle_upload, search::search_with_bboxes, ready::is_ready};
+use cache::SearchCache;
+use jobs::Job;
+use types::{AppState, IdJobMap, IdToFilenameMap, SharedRegistry};
+use handlers::{
+    documents::{
+        delete_document, get_document, get_filter_report, list_documents, reindex_document,
+    },
+    jobs::{get_job, job_events},
+    library::search_library,
+    ready::is_ready,
+    search::{search_cache_stats, search_with_bboxes},
+    upload::handle_upload,
+};
+
+pub const COLLECTION: &str = "embedded_pdfs";
+
+#[derive(Clone, Debug, ValueEnum)]
+enum StoreKind {
+    Qdrant,
+    Memory,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+enum EmbedderKind {
+    /// ONNX model and tokenizer files in `--model-dir`
+    Onnx,
+    /// fastembed built-in model named by `--model`
+    Fastembed,
+    /// Deterministic feature hashing; no model files needed
+    Hashing,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Vector store backend
+    #[arg(long, value_enum, default_value = "qdrant")]
+    store: StoreKind,
+
+    #[arg(long, default_value = "http://localhost:6334")]
+    qdrant_url: String,
+
+    /// Snapshot file for the memory store; kept purely in memory when omitted
+    #[arg(long)]
+    store_path: Option<String>,
+
+    /// Directory holding the document registry and uploaded PDFs
+    #[arg(long, default_value = "data")]
+    data_dir: String,
+
+    /// Embedding backend
+    #[arg(long, value_enum, default_value = "onnx")]
+    embedder: EmbedderKind,
+
+    #[arg(long, default_value = "model")]
+    model_dir: String,
+
+    /// fastembed model code, e.g. `Qdrant/all-MiniLM-L6-v2-onnx`
+    #[arg(long, default_value = "Qdrant/all-MiniLM-L6-v2-onnx")]
+    model: String,
+
+    /// Vector size of the hashing embedder
+    #[arg(long, default_value_t = 384)]
+    hash_dim: usize,
+
+    /// Text prepended to search queries; defaults to what the model expects
+    #[arg(long)]
+    query_prefix: Option<String>,
+
+    /// Text prepended to document chunks; defaults to what the model expects
+    #[arg(long)]
+    passage_prefix: Option<String>,
+
+    /// Model instances embedding documents in parallel; queries get one more
+    #[arg(long, default_value_t = 2)]
+    embedding_sessions: usize,
+
+    /// Size limit of the on-disk embedding cache in megabytes; 0 disables it
+    #[arg(long, default_value_t = 512)]
+    embedding_cache_mb: u64,
+
+    /// Number of recent query embeddings and search responses kept in memory
+    #[arg(long, default_value_t = 1024)]
+    query_cache_size: usize,
+
+    /// fastembed cross-encoder enabling `rerank=true` searches, e.g. `BAAI/bge-reranker-base`
+    #[arg(long)]
+    reranker: Option<String>,
+
+    /// Chunking method of uploads that do not choose one: characters,
+    /// tokens, sentence_window or paragraph
+    #[arg(long, default_value = "characters")]
+    chunking: ChunkingMethod,
+
+    /// Chunk size in the method's unit; the method's default when omitted
+    #[arg(long)]
+    chunk_size: Option<usize>,
+
+    /// Chunk overlap in the method's unit; the method's default when omitted
+    #[arg(long)]
+    chunk_overlap: Option<usize>,
+
+    /// Clean-up of page text before chunking: none, text or headers
+    #[arg(long, default_value = "none")]
+    chunk_cleaning: Cleaning,
+
+    /// Let chunks of uploads that do not choose continue across page breaks
+    #[arg(long)]
+    cross_page_chunks: bool,
+
+    /// Quality rules chunks must pass, e.g. "min_chars=20,code"; unlisted
+    /// rules are off and "none" keeps every chunk
+    #[arg(
+        long,
+        default_value = "min_chars=50,min_words=8,min_alpha_ratio=0.5,artifacts,max_ellipses=2,code"
+    )]
+    chunk_filter: ChunkFilter,
+
+    /// Chunk uploads that do not choose as children of paragraph sections
+    /// of up to this many characters
+    #[arg(long)]
+    parent_chunk_size: Option<usize>,
+
+    /// Collapse near-duplicate chunks of uploads that do not choose, such as
+    /// repeated notices, into one
+    #[arg(long)]
+    dedup_chunks: bool,
+
+    /// PDF libraries to extract text with, in order; pages that come out
+    /// empty or garbled are retried with the next one
+    #[arg(long, value_delimiter = ',', default_value = "pdfium,pdf_oxide,lopdf")]
+    extractors: Vec<Backend>,
+}
+
+fn embedder_config(args: &Args) -> anyhow::Result<EmbedderConfig> {
+    Ok(match args.embedder {
+        EmbedderKind::Onnx => EmbedderConfig::OnnxDir(args.model_dir.clone().into()),
+        EmbedderKind::Fastembed => EmbedderConfig::Fastembed(
+            args.model.parse::<EmbeddingModel>().map_err(anyhow::Error::msg)?,
+        ),
+        EmbedderKind::Hashing => EmbedderConfig::Hashing { dim: args.hash_dim },
+    })
+}
+
+fn build_reranker(args: &Args) -> anyhow::Result<Option<Arc<dyn Reranker>>> {
+    let Some(model) = &args.reranker else {
+        return Ok(None);
+    };
+    let model = model.parse::<RerankerModel>().map_err(anyhow::Error::msg)?;
+    let reranker = FastembedReranker::new(model)?;
+    println!("Reranking with {}", reranker.model_id());
+    Ok(Some(Arc::new(reranker)))
+}
+
+fn build_embedder(args: &Args) -> anyhow::Result<(Arc<dyn Embedder>, Prompts)> {
+    let mut embedder = embed::load(&embedder_config(args)?, args.embedding_sessions)?;
+
+    let defaults = Prompts::for_model(embedder.model_id());
+    let prompts = Prompts {
+        query: args.query_prefix.clone().unwrap_or(defaults.query),
+        passage: args.passage_prefix.clone().unwrap_or(defaults.passage),
+    };
+
+    if args.embedding_cache_mb > 0 {
+        let cache = EmbeddingCache::open(
+            Path::new(&args.data_dir).join("embedding-cache"),
+            args.embedding_cache_mb * 1024 * 1024,
+        )?;
+        embedder = Arc::new(CachedEmbedder::new(embedder, cache));
+    }
+
+    // Outside the cache, so cached vectors are keyed by the prompted text
+    let embedder = Arc::new(PromptedEmbedder::new(embedder, prompts.clone())?);
+    Ok((embedder, prompts))
+}
+
+fn chunking_config(args: &Args) -> ChunkingConfig {
+    let defaults = ChunkingConfig::new(args.chunking);
+    ChunkingConfig {
+        method: args.chunking,
+        size: args.chunk_size.unwrap_or(defaults.size),
+        overlap: args.chunk_overlap.unwrap_or(defaults.overlap),
+        cleaning: args.chunk_cleaning,
+        cross_page: args.cross_page_chunks,
+        filter: args.chunk_filter,
+        parent_size: args.parent_chunk_size,
+        dedup: args.dedup_chunks,
+    }
+}
+
+fn build_store(args: &Args) -> anyhow::Result<Arc<dyn VectorStore>> {
+    Ok(match args.store {
+        StoreKind::Qdrant => Arc::new(QdrantStore::connect(&args.qdrant_url)?),
+        StoreKind::Memory => match &args.store_path {
+            Some(path) => Arc::new(MemoryStore::open(path)?),
+            None => Arc::new(MemoryStore::new()),
+        },
+    })
+}
 
 #[tokio::main]
 async fn main() {
-    let id_map: IdToFilenameMap = Arc::new(RwLock::new(HashMap::new()));
-    let bytes_map: IdToBytesMap = Arc::new(RwLock::new(HashMap::new()));
-    let ready_set: IdReadyMap = Arc::new(RwLock::new(HashSet::new()));
+    let args = Args::parse();
+
+    let mut registry = Registry::open(&args.data_dir).expect("Failed to open document registry");
+    let (id_map, jobs) = restore_state(&mut registry).expect("Failed to restore documents");
 
-    let qdrant_client = Qdrant::from_url("http://localhost:6334")
-        .build()
-        .expect("Failed to connect to Qdrant");
+    let id_map: IdToFilenameMap = Arc::new(RwLock::new(id_map));
+    let jobs: IdJobMap = Arc::new(RwLock::new(
+        jobs.into_iter()
+            .map(|(id, job)| (id, watch::channel(job).0))
+            .collect(),
+    ));
+    let registry: SharedRegistry = Arc::new(RwLock::new(registry));
 
-    qdrant::delete_all_collections(&qdrant_client).await;
-    let _ = qdrant::init_collection(&qdrant_client, "embedded_pdfs").await;
+    let (embedder, prompts) = build_embedder(&args).expect("Failed to load embedding model");
+    let metadata = CollectionMetadata {
+        model_id: embedder.model_id().to_string(),
+        query_prefix: prompts.query,
+        passage_prefix: prompts.passage,
+    };
+    println!("Embedding with {} ({} dimensions)", embedder.model_id(), embedder.dim());
+    if let Some(tokenizer) = embedder.tokenizer() {
+        println!("Chunks over {} tokens are split", tokenizer.max_tokens());
+    }
+    let chunking = chunking_config(&args);
+    chunking
+        .strategy(embedder.tokenizer())
+        .expect("Invalid chunking settings");
+    chunking
+        .parent_strategy()
+        .expect("Invalid parent chunk size");
+    let query_embedder = Arc::new(QueryCachedEmbedder::new(embedder, args.query_cache_size));
+    let search_cache = Arc::new(SearchCache::new(query_embedder.clone(), args.query_cache_size));
+    let embedder: Arc<dyn Embedder> = query_embedder;
+    let reranker = build_reranker(&args).expect("Failed to load reranker");
+
+    let store = build_store(&args).expect("Failed to open vector store");
+    store::ensure_collection(store.as_ref(), COLLECTION, embedder.dim(), &metadata)
+        .await
+        .expect("Failed to create collection");
+
+    let keywords = Arc::new(
+        LexicalIndex::open(Path::new(&args.data_dir).join("keywords"))
+            .expect("Failed to open keyword index"),
+    );
+    backfill_keywords(store.as_ref(), &keywords, &id_map).await;
 
     let state = AppState {
         id_map,
-        bytes_map,
-        qdrant: Arc::new(qdrant_client),
-        ready_set,
+        store: store.clone(),
+        embedder,
+        keywords,
+        search_cache,
+        reranker,
+        chunking,
+        extractor: Arc::new(FallbackExtractor::new(&args.extractors)),
+        jobs,
+        registry,
     };
 
     let app = Router::new()
         .route("/", get(index))
         .route("/upload", post(handle_upload))
         .route("/api/search", get(search_with_bboxes))
+        .route("/api/search/cache", get(search_cache_stats))
         .route("/api/ready", get(is_ready))
+        .route("/api/jobs/:id", get(get_job))
+        .route("/api/jobs/:id/events", get(job_events))
+        .route("/api/library/search", get(search_library))
+        .route("/api/documents", get(list_documents))
+        .route("/api/documents/:id", get(get_document).delete(delete_document))
+        .route("/api/documents/:id/reindex", post(reindex_document))
+        .route("/api/documents/:id/filter-report", get(get_filter_report))
         .nest_service("/static", ServeDir::new("static"))
         .layer(DefaultBodyLimit::max(100 * 1024 * 1024))
         .with_state(state);
@@ -62,6 +320,75 @@ async fn main() {
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    if let Err(e) = store.flush().await {
+        eprintln!("Failed to save vector store: {:?}", e);
+    }
+}
+
+/// Rebuilds the id map and job states from the registry. Documents that
+/// were still processing when the server stopped are marked as failed,
+/// unless they were being re-indexed: those keep their previous index.
+fn restore_state(
+    registry: &mut Registry,
+) -> anyhow::Result<(HashMap<String, String>, HashMap<String, Job>)> {
+    let mut id_map = HashMap::new();
+    let mut jobs = HashMap::new();
+
+    let interrupted: Vec<String> = registry
+        .list()
+        .into_iter()
+        .filter(|record| record.status == DocumentStatus::Processing)
+        .map(|record| record.id.clone())
+        .collect();
+    for id in interrupted {
+        registry.update(&id, |record| {
+            if record.unique_filename.is_some() {
+                record.status = DocumentStatus::Ready;
+                record.error = Some(
+                    "Re-indexing interrupted by server restart; the previous index is kept"
+                        .to_string(),
+                );
+            } else {
+                record.status = DocumentStatus::Failed;
+                record.error = Some("Interrupted by server restart".to_string());
+            }
+        })?;
+    }
+
+    for record in registry.list() {
+        match (record.status, &record.unique_filename) {
+            (DocumentStatus::Ready, Some(unique_filename)) => {
+                id_map.insert(record.id.clone(), unique_filename.clone());
+                jobs.insert(record.id.clone(), Job::done(&record.id));
+            }
+            _ => {
+                let error = record.error.clone().unwrap_or_else(|| "Unknown error".to_string());
+                jobs.insert(record.id.clone(), Job::failed(&record.id, error));
+            }
+        }
+    }
+
+    println!("Restored {} documents", jobs.len());
+    Ok((id_map, jobs))
+}
+
+/// Builds keyword indexes for ready documents that have none, e.g. documents
+/// ingested before keyword search existed.
+async fn backfill_keywords(
+    store: &dyn VectorStore,
+    keywords: &LexicalIndex,
+    id_map: &IdToFilenameMap,
+) {
+    let filenames: Vec<String> = id_map.read().await.values().cloned().collect();
+    for filename in filenames {
+        if keywords.contains(&filename) {
+            continue;
+        }
+        if let Err(e) = store::index_keywords(store, keywords, COLLECTION, &filename).await {
+            eprintln!("Failed to build keyword index for {}: {:?}", filename, e);
+        }
+    }
 }
 
 async fn shutdown_signal() {
@@ -1,24 +1,41 @@
 // src/types.rs
 
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use qdrant_client::Qdrant;
+use vb::chunk::{ChunkLocation, ChunkingConfig, ParentChunk};
+use vb::embed::Embedder;
+use vb::extract::{Backend, FallbackExtractor};
+use vb::lexical::LexicalIndex;
+pub use vb::pdfium::CharBbox;
+use vb::rerank::Reranker;
+use vb::registry::{DocumentRecord, DocumentStatus, Registry};
+use vb::store::VectorStore;
 use serde::{Deserialize, Serialize};
 
+use crate::cache::SearchCache;
+use crate::jobs::JobHandle;
+
 // --- Type aliases for shared state maps ---
 pub type IdToFilenameMap = Arc<RwLock<HashMap<String, String>>>;
-pub type IdToBytesMap = Arc<RwLock<HashMap<String, Vec<u8>>>>;
-pub type IdReadyMap = Arc<RwLock<HashSet<String>>>;
+pub type IdJobMap = Arc<RwLock<HashMap<String, JobHandle>>>;
+pub type SharedRegistry = Arc<RwLock<Registry>>;
 
 // --- App state shared across handlers ---
 #[derive(Clone)]
 pub struct AppState {
     pub id_map: IdToFilenameMap,
-    pub bytes_map: IdToBytesMap,
-    pub qdrant: Arc<Qdrant>,
-    pub ready_set: IdReadyMap,
+    pub store: Arc<dyn VectorStore>,
+    pub embedder: Arc<dyn Embedder>,
+    pub keywords: Arc<LexicalIndex>,
+    pub search_cache: Arc<SearchCache>,
+    /// Cross-encoder for `rerank=true` searches, when configured.
+    pub reranker: Option<Arc<dyn Reranker>>,
+    /// Chunking of uploads that do not choose their own.
+    pub chunking: ChunkingConfig,
+    pub extractor: Arc<FallbackExtractor>,
+    pub jobs: IdJobMap,
+    pub registry: SharedRegistry,
 }
 
 // --- Request types ---
@@ -26,6 +43,30 @@ pub struct AppState {
 pub struct SearchWithBboxQuery {
     pub id: String,
     pub q: String,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Minimum vector score of vector hits; keyword hits are returned
+    /// regardless, without a vector score if theirs is lower.
+    pub min_score: Option<f32>,
+    /// Weight of vector search against keyword search, from 0.0 to 1.0.
+    pub semantic_weight: Option<f32>,
+    /// Reorders the hits with the cross-encoder reranker.
+    pub rerank: Option<bool>,
+    /// Number of top hits the reranker scores.
+    pub rerank_candidates: Option<usize>,
+    /// Returns each parent section once, through its best matching chunk.
+    pub parents: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct LibrarySearchQuery {
+    pub q: String,
+    /// Comma-separated document ids to search; all documents when omitted.
+    pub ids: Option<String>,
+    /// Comma-separated tags; documents carrying any of them are searched.
+    pub tags: Option<String>,
+    pub limit: Option<usize>,
+    pub per_document: Option<usize>,
 }
 
 // --- Response types ---
@@ -34,22 +75,108 @@ pub struct UploadResponse {
     pub id: String,
 }
 
-#[derive(Serialize)]
-pub struct SearchResult {
-    pub page: i64,
-    pub text: String,
+/// Body of `/api/search`. Bump [`SEARCH_RESPONSE_VERSION`] on breaking changes.
+#[derive(Clone, Serialize)]
+pub struct SearchResponse {
+    pub version: u32,
+    pub query: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub semantic_weight: f32,
+    /// Whether hits are ordered by `rerank_score`.
+    pub reranked: bool,
+    pub hits: Vec<SearchHit>,
 }
 
-#[derive(Serialize)]
-pub struct CharBbox {
-    pub x: f32,
-    pub y: f32,
-    pub width: f32,
-    pub height: f32,
+pub const SEARCH_RESPONSE_VERSION: u32 = 3;
+
+#[derive(Clone, Serialize)]
+pub struct SearchHit {
+    /// 1-based position across pages of results.
+    pub rank: usize,
+    pub chunk_id: String,
+    /// Page the chunk starts on.
+    pub page: usize,
+    /// Page the chunk ends on; differs from `page` for cross-page chunks.
+    pub end_page: usize,
+    /// Reciprocal rank fusion score; hits are ordered by it unless reranked.
+    pub score: f32,
+    /// Similarity between the query and chunk embeddings, if the chunk was
+    /// among the vector hits.
+    pub vector_score: Option<f32>,
+    /// BM25 score, if the chunk was among the keyword hits.
+    pub lexical_score: Option<f32>,
+    /// Cross-encoder relevance, if the hits were reranked.
+    pub rerank_score: Option<f32>,
+    /// How closely the chunk text matched the page text, for chunks ingested
+    /// before highlights were precomputed and located at search time; `None`
+    /// when the highlights were computed at ingest. The weakest page counts
+    /// for cross-page chunks.
+    pub fuzzy_score: Option<f32>,
+    /// Matched page text, or the chunk text when no match was found.
+    pub snippet: String,
+    /// Highlight rectangles on every page the chunk was found on.
+    pub highlights: Vec<PageHighlight>,
+    /// Section around the chunk, for documents chunked with parents. The
+    /// highlights only cover the chunk itself.
+    pub parent: Option<ParentChunk>,
+    /// Other places with nearly the same text, for documents ingested with
+    /// deduplication.
+    pub duplicates: Vec<ChunkLocation>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct PageHighlight {
     pub page: usize,
     pub rects: Vec<CharBbox>,
-}
\ No newline at end of file
+}
+#[derive(Serialize)]
+pub struct DocumentInfo {
+    pub id: String,
+    pub filename: String,
+    pub tags: Vec<String>,
+    pub chunking: ChunkingConfig,
+    pub status: DocumentStatus,
+    pub error: Option<String>,
+    pub pages: usize,
+    pub chunks: usize,
+    pub dropped_chunks: usize,
+    pub page_backends: BTreeMap<u16, Backend>,
+    pub content_hash: String,
+    pub created_at: u64,
+}
+
+impl From<&DocumentRecord> for DocumentInfo {
+    fn from(record: &DocumentRecord) -> Self {
+        Self {
+            id: record.id.clone(),
+            filename: record.filename.clone(),
+            tags: record.tags.clone(),
+            chunking: record.chunking,
+            status: record.status,
+            error: record.error.clone(),
+            pages: record.pages,
+            chunks: record.chunks,
+            dropped_chunks: record.dropped_chunks,
+            page_backends: record.page_backends.clone(),
+            content_hash: record.content_hash.clone(),
+            created_at: record.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LibraryHit {
+    pub page: u16,
+    pub score: f32,
+    pub snippet: String,
+}
+
+#[derive(Serialize)]
+pub struct LibraryDocumentHits {
+    pub document_id: String,
+    pub filename: String,
+    /// Score of the best hit in this document.
+    pub score: f32,
+    pub hits: Vec<LibraryHit>,
+}
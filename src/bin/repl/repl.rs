@@ -1,15 +1,23 @@
 use axum::extract::Query;
 use axum::{Json, Router, body::Body, http::StatusCode, response::Html, routing::get};
-use clap::{Parser, arg, command};
-use qdrant_client::Qdrant;
+use clap::{Parser, ValueEnum};
 use serde::Deserialize;
 use serde::Serialize;
 use std::fs;
 use std::io::{self, BufRead, Write};
+use std::sync::Arc;
 use tower_http::services::ServeDir;
-use vb::chunk;
-use vb::embed;
-use vb::qdrant;
+use vb::chunk::{self, FilterReport};
+use vb::embed::{self, Embedder, EmbedderConfig};
+use vb::memory_store::MemoryStore;
+use vb::qdrant::QdrantStore;
+use vb::store::{self, CollectionMetadata, SearchOptions, VectorStore};
+
+#[derive(Clone, Debug, ValueEnum)]
+enum StoreKind {
+    Qdrant,
+    Memory,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -19,6 +27,17 @@ struct Args {
 
     #[arg(short, long)]
     search: Option<String>,
+
+    /// Vector store backend
+    #[arg(long, value_enum, default_value = "qdrant")]
+    store: StoreKind,
+
+    #[arg(long, default_value = "http://localhost:6334")]
+    qdrant_url: String,
+
+    /// Snapshot file for the memory store; kept purely in memory when omitted
+    #[arg(long)]
+    store_path: Option<String>,
 }
 
 // Response structure for the search API
@@ -34,8 +53,16 @@ struct SearchQuery {
     q: String,
 }
 
+#[derive(Clone)]
+struct ServerState {
+    collection_name: String,
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
+}
+
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
     let banner = r#"
  ██████╗ ██╗   ██╗███████╗██████╗ ██╗   ██╗
 ██╔═══██╗██║   ██║██╔════╝██╔══██╗╚██╗ ██╔╝
@@ -48,14 +75,29 @@ async fn main() {
     println!("{}", banner);
     println!("Type 'help' for available commands, 'exit' to quit");
 
-    if let Err(e) = run_repl().await {
+    if let Err(e) = run_repl(&args).await {
         eprintln!("Error: {}", e);
     }
 }
 
-async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
+fn build_store(args: &Args) -> anyhow::Result<Arc<dyn VectorStore>> {
+    Ok(match args.store {
+        StoreKind::Qdrant => Arc::new(QdrantStore::connect(&args.qdrant_url)?),
+        StoreKind::Memory => match &args.store_path {
+            Some(path) => Arc::new(MemoryStore::open(path)?),
+            None => Arc::new(MemoryStore::new()),
+        },
+    })
+}
+
+async fn run_repl(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
     let stdin = io::stdin();
     let mut reader = stdin.lock();
+    // Chunks dropped while processing the last file
+    let mut last_report: Option<FilterReport> = None;
+    // Loading builds the ONNX sessions, so it is done once for all commands
+    let embedder = embed::load(&EmbedderConfig::default(), 1)?;
+    let store = build_store(args)?;
 
     loop {
         print!("> ");
@@ -72,7 +114,7 @@ async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
         // Parse the command
         let parts: Vec<&str> = input.split_whitespace().collect();
 
-        match parts.first().map(|s| *s) {
+        match parts.first().copied() {
             Some("exit") | Some("quit") => {
                 println!("Goodbye!");
                 break;
@@ -86,10 +128,15 @@ async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
                     continue;
                 }
                 let file_path = parts[1];
-                if let Err(e) = process_file(file_path).await {
-                    eprintln!("Error processing file: {}", e);
+                match process_file(&embedder, store.as_ref(), file_path).await {
+                    Ok(report) => last_report = Some(report),
+                    Err(e) => eprintln!("Error processing file: {}", e),
                 }
             }
+            Some("dropped") => match &last_report {
+                Some(report) => print_dropped(report),
+                None => println!("No file processed yet."),
+            },
             Some("search") => {
                 if parts.len() < 3 {
                     println!("Usage: search <collection_name> <query>");
@@ -97,7 +144,7 @@ async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 let collection_name = parts[1];
                 let query = parts[2..].join(" ");
-                if let Err(e) = run_search_repl(collection_name, query).await {
+                if let Err(e) = run_search_repl(&embedder, store.as_ref(), collection_name, query).await {
                     eprintln!("Error searching: {}", e);
                 }
             }
@@ -108,7 +155,7 @@ async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 let file_path = parts[1];
                 let collection_name = parts[2];
-                if let Err(e) = start_server(file_path, collection_name).await {
+                if let Err(e) = start_server(embedder.clone(), store.clone(), file_path, collection_name).await {
                     eprintln!("Error starting server: {}", e);
                 }
             }
@@ -128,6 +175,7 @@ async fn run_repl() -> Result<(), Box<dyn std::error::Error>> {
 fn print_help() {
     println!("Available commands:");
     println!("  file <path>                        - Process and index a file");
+    println!("  dropped                            - List chunks dropped from the last file");
     println!("  search <collection> <query>        - Search in a collection");
     println!(
         "  serve <file_path> <collection>     - Start web server with PDF viewer and search API"
@@ -136,41 +184,65 @@ fn print_help() {
     println!("  exit/quit                          - Exit the program");
 }
 
-async fn process_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn process_file(
+    embedder: &Arc<dyn Embedder>,
+    client: &dyn VectorStore,
+    file_path: &str,
+) -> Result<FilterReport, Box<dyn std::error::Error>> {
     println!("Processing file: {}", file_path);
-    let chunks = chunk::extract_and_chunk(chunk::PdfSource::Path(file_path.to_string()))?;
-    let embedded_chunks = embed::get_embeddings(chunks)?;
-    let client = qdrant::setup_qdrant().await?;
-    let response = qdrant::store_embeddings(&client, "repl", file_path, embedded_chunks).await?;
+    let (chunks, report) = chunk::extract_and_chunk(chunk::PdfSource::Path(file_path.to_string()))?;
+    let embedded_chunks = embed::get_embeddings(embedder.as_ref(), chunks)?;
+    let metadata = CollectionMetadata {
+        model_id: embedder.model_id().to_string(),
+        ..CollectionMetadata::default()
+    };
+    // Fails rather than mixing vectors of another model into the collection
+    store::ensure_collection(client, "repl", embedder.dim(), &metadata).await?;
+    let response = store::store_embeddings(client, "repl", file_path, embedded_chunks).await?;
 
     println!("File processed successfully!");
     dbg!(response);
+    if !report.dropped.is_empty() {
+        println!("Dropped {} chunks, type 'dropped' to list them", report.dropped.len());
+    }
 
-    Ok(())
+    Ok(report)
+}
+
+fn print_dropped(report: &FilterReport) {
+    println!("\nDropped Chunks:");
+    println!("===============");
+    for (rule, count) in report.counts() {
+        println!("{}: {}", rule.name(), count);
+    }
+    for chunk in &report.dropped {
+        println!("-----");
+        println!("page {}-{} ({})", chunk.page, chunk.end_page, chunk.rule.name());
+        println!("{}", chunk.text);
+    }
 }
 
 // REPL version of search (prints to console)
-async fn run_search_repl(file_name: &str, query: String) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_search_repl(
+    embedder: &Arc<dyn Embedder>,
+    client: &dyn VectorStore,
+    file_name: &str,
+    query: String,
+) -> Result<(), Box<dyn std::error::Error>> {
     let query = query.trim();
     if query.is_empty() {
         println!("No query entered.");
         return Ok(());
     }
 
-    let client = Qdrant::from_url("http://localhost:6334").build()?;
-    let resp = qdrant::run_query(&client, "repl", file_name, query).await?;
+    let hits = store::run_query(client, embedder, "repl", file_name, query, &SearchOptions::default()).await?;
 
     println!("\nSearch Results:");
     println!("===============");
-    for point in resp.result {
-        if let Some(text_value) = point.payload.get("text") {
-            let page = point.payload.get("page").unwrap();
-            if let Some(text) = text_value.as_str() {
-                println!("-----");
-                println!("{:?}", page);
-                println!("{}", text);
-            }
-        }
+    for hit in hits {
+        println!("-----");
+        println!("{:?}", hit.payload.page);
+        println!("{}", hit.payload.text);
     }
 
     Ok(())
@@ -178,6 +250,8 @@ async fn run_search_repl(file_name: &str, query: String) -> Result<(), Box<dyn s
 
 // API version of search (returns JSON)
 async fn run_search_api(
+    embedder: &Arc<dyn Embedder>,
+    client: &dyn VectorStore,
     file_name: &str,
     query: String,
 ) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
@@ -186,33 +260,15 @@ async fn run_search_api(
         return Ok(vec![]);
     }
 
-    let client = Qdrant::from_url("http://localhost:6334").build()?;
-    let resp = qdrant::run_query(&client, "repl", file_name, &query).await?;
-
-    let mut results = Vec::new();
-
-    for point in resp.result {
-        if let Some(text_value) = point.payload.get("text") {
-            if let Some(page_value) = point.payload.get("page") {
-                if let Some(text) = text_value.as_str() {
-                    // Extract page number - handle different number types
-                    use qdrant_client::qdrant::value::Kind;
-
-                    let page = match &page_value.kind {
-                        Some(Kind::DoubleValue(d)) => *d as i64,
-                        Some(Kind::IntegerValue(i)) => *i,
-                        Some(Kind::StringValue(s)) => s.parse::<i64>().unwrap_or(1),
-                        _ => 1,
-                    };
-
-                    results.push(SearchResult {
-                        page,
-                        text: text.to_string(),
-                    });
-                }
-            }
-        }
-    }
+    let hits = store::run_query(client, embedder, "repl", file_name, query, &SearchOptions::default()).await?;
+
+    let results = hits
+        .into_iter()
+        .map(|hit| SearchResult {
+            page: hit.payload.page as i64,
+            text: hit.payload.text,
+        })
+        .collect();
 
     Ok(results)
 }
@@ -220,7 +276,7 @@ async fn run_search_api(
 // Handler for /api/search endpoint
 async fn search_handler(
     Query(params): Query<SearchQuery>,
-    axum::extract::State(collection_name): axum::extract::State<String>,
+    axum::extract::State(state): axum::extract::State<ServerState>,
 ) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
     let query = params.q.trim();
 
@@ -231,7 +287,7 @@ async fn search_handler(
         ));
     }
 
-    match run_search_api(&collection_name, query.to_string()).await {
+    match run_search_api(&state.embedder, state.store.as_ref(), &state.collection_name, query.to_string()).await {
         Ok(results) => Ok(Json(results)),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -241,6 +297,8 @@ async fn search_handler(
 }
 
 async fn start_server(
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
     file_path: &str,
     collection_name: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -264,7 +322,11 @@ async fn start_server(
             }),
         )
         .route("/api/search", get(search_handler))
-        .with_state(collection_name.clone());
+        .with_state(ServerState {
+            collection_name: collection_name.clone(),
+            embedder,
+            store,
+        });
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
 
@@ -1,15 +1,91 @@
-use crate::extract::Page;
-use anyhow::Result;
+use crate::dedup;
+use crate::embed::ModelTokenizer;
+use crate::extract::{Backend, File, Page};
+use crate::pdfium::CharBbox;
+use crate::progress::{self, Progress, ProgressFn};
+use anyhow::{Result, anyhow, bail};
 use lopdf::Document;
 use regex::Regex;
-use text_splitter::TextSplitter;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use text_splitter::{ChunkConfig, ChunkSizer, Characters, TextSplitter};
+use tokenizers::Tokenizer;
 use unicode_segmentation::UnicodeSegmentation;
 use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub content: String,
+    /// Page the chunk starts on.
     pub page: u16,
+    /// Page the chunk ends on; only differs from `page` for cross-page chunks.
+    pub end_page: u16,
+    /// Which characters of `content` come from which page.
+    pub spans: Vec<PageSpan>,
+    /// Section the chunk was cut from, when chunking with parents.
+    pub parent: Option<ParentChunk>,
+    /// Other places with nearly the same text, collapsed into this chunk.
+    pub duplicates: Vec<ChunkLocation>,
+}
+
+/// Where a chunk's text appears in the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkLocation {
+    pub page: u16,
+    pub end_page: u16,
+}
+
+/// Larger section of text around child chunks, returned for context but not
+/// embedded itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParentChunk {
+    /// Unique within the document.
+    pub id: usize,
+    pub content: String,
+    pub page: u16,
+    pub end_page: u16,
+}
+
+impl Chunk {
+    /// A chunk lying entirely on `page`.
+    pub fn on_page(content: impl Into<String>, page: u16) -> Self {
+        let content = content.into();
+        let spans = vec![PageSpan {
+            page,
+            start: 0,
+            end: content.chars().count(),
+            pdf_chars: None,
+            rects: None,
+        }];
+        Self {
+            content,
+            page,
+            end_page: page,
+            spans,
+            parent: None,
+            duplicates: vec![],
+        }
+    }
+}
+
+/// Characters `start..end` of a chunk's text, taken from `page`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageSpan {
+    pub page: u16,
+    pub start: usize,
+    pub end: usize,
+    /// The chars of the pdfium text page the span was extracted from.
+    /// Unknown when the page was not extracted with pdfium or its text was
+    /// rewritten by cleaning.
+    #[serde(default)]
+    pub pdf_chars: Option<Range<usize>>,
+    /// Highlight rectangles of the span, once computed from the PDF.
+    #[serde(default)]
+    pub rects: Option<Vec<CharBbox>>,
 }
 
 pub enum PdfSource {
@@ -17,288 +93,864 @@ pub enum PdfSource {
     Bytes(Vec<u8>),
 }
 
-use rayon::prelude::*;
+/// How page text is cut into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkingMethod {
+    /// Semantic splitting up to `size` characters.
+    Characters,
+    /// Semantic splitting up to `size` tokens of the embedding model.
+    Tokens,
+    /// Whole sentences until a window reaches `size` characters; `overlap`
+    /// sentences are repeated at the start of the next window.
+    SentenceWindow,
+    /// Blank-line separated paragraphs packed up to `size` characters.
+    Paragraph,
+}
 
-pub fn extract_and_chunk(pdf_source: PdfSource) -> Result<Vec<Chunk>> {
-    // Load the PDF
-    let doc = match pdf_source {
-        PdfSource::Path(path) => Document::load(path)?,
-        PdfSource::Bytes(vec) => Document::load_mem(&vec)?,
-    };
+impl FromStr for ChunkingMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "characters" => Self::Characters,
+            "tokens" => Self::Tokens,
+            "sentence_window" => Self::SentenceWindow,
+            "paragraph" => Self::Paragraph,
+            _ => bail!(
+                "Unknown chunking method '{}', expected characters, tokens, sentence_window or paragraph",
+                s
+            ),
+        })
+    }
+}
 
-    let pages = doc.get_pages();
-    
-    // Collect page numbers into a Vec for parallel iteration
-    let page_numbers: Vec<u32> = pages.keys().copied().collect();
-    
-    let splitter = TextSplitter::new(500); // chunk size
+/// Clean-up applied to page text before it is chunked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cleaning {
+    /// Text as extracted.
+    #[default]
+    None,
+    /// [`clean_pdf_text_robust`]: joins broken lines and drops table of
+    /// contents leaders, ligatures and symbol-only lines.
+    Text,
+    /// Like `Text`, also dropping section headers.
+    Headers,
+}
 
-    // Parallel iteration over page numbers
-    let chunks: Vec<Chunk> = page_numbers
-        .par_iter()
-        .flat_map(|&page_num| {
-            // Extract text for this page
-            if let Ok(text) = doc.extract_text(&[page_num]) {
-                splitter
-                    .chunks(&text)
-                    .filter_map(|chunk| {
-                        if is_valid_chunk(chunk) {
-                            Some(Chunk {
-                                content: chunk.to_string(),
-                                page: page_num as u16,
-                            })
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            } else {
-                Vec::new()
-            }
+impl Cleaning {
+    /// Cleans each paragraph separately, so paragraph breaks survive.
+    fn apply(self, text: &str) -> String {
+        let remove_headers = match self {
+            Self::None => return text.to_string(),
+            Self::Text => false,
+            Self::Headers => true,
+        };
+        paragraphs(text)
+            .into_iter()
+            .map(|paragraph| clean_pdf_text_robust(&text[paragraph], remove_headers))
+            .filter(|paragraph| !paragraph.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl FromStr for Cleaning {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "none" => Self::None,
+            "text" => Self::Text,
+            "headers" => Self::Headers,
+            _ => bail!("Unknown cleaning '{}', expected none, text or headers", s),
         })
-        .collect();
-    Ok(chunks)
+    }
+}
+
+/// Quality rule a chunk can be dropped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterRule {
+    MinChars,
+    MinWords,
+    MinAlphaRatio,
+    /// Lone back matter titles such as "Index".
+    Artifact,
+    MaxEllipses,
+    Code,
 }
 
-// Optimized validation function
-fn is_valid_chunk(text: &str) -> bool {
-    let trimmed = text.trim();
-    if trimmed.len() < 50 { 
-        return false; 
+impl FilterRule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MinChars => "min_chars",
+            Self::MinWords => "min_words",
+            Self::MinAlphaRatio => "min_alpha_ratio",
+            Self::Artifact => "artifacts",
+            Self::MaxEllipses => "max_ellipses",
+            Self::Code => "code",
+        }
     }
+}
+
+/// Rules a chunk must pass to be embedded. `None` and `false` turn a rule
+/// off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChunkFilter {
+    /// Characters of the trimmed text.
+    pub min_chars: Option<usize>,
+    pub min_words: Option<usize>,
+    /// Share of alphabetic characters, from 0 to 1.
+    pub min_alpha_ratio: Option<f32>,
+    pub max_ellipses: Option<usize>,
+    pub artifacts: bool,
+    /// Drops chunks that look like source code.
+    pub code: bool,
+}
 
-    // Count words, alphabetic chars, and sentence endings in one pass
-    let mut words = 0;
-    let mut alpha = 0;
-    let mut sentence_endings = 0;
+impl ChunkFilter {
+    /// Every rule off.
+    pub fn none() -> Self {
+        Self {
+            min_chars: None,
+            min_words: None,
+            min_alpha_ratio: None,
+            max_ellipses: None,
+            artifacts: false,
+            code: false,
+        }
+    }
 
-    let mut last_char_was_whitespace = true;
-    for c in trimmed.chars() {
-        if c.is_alphabetic() { 
-            alpha += 1; 
+    /// The first rule rejecting `text`, if any.
+    pub fn check(&self, text: &str) -> Option<FilterRule> {
+        let trimmed = text.trim();
+        if self.min_chars.is_some_and(|min| trimmed.len() < min) {
+            return Some(FilterRule::MinChars);
+        }
+        if self
+            .min_words
+            .is_some_and(|min| trimmed.split_whitespace().count() < min)
+        {
+            return Some(FilterRule::MinWords);
+        }
+        if let Some(min) = self.min_alpha_ratio {
+            let alpha = trimmed.chars().filter(|c| c.is_alphabetic()).count();
+            if (alpha as f32) < min * trimmed.len() as f32 {
+                return Some(FilterRule::MinAlphaRatio);
+            }
         }
-        if matches!(c, '.' | '!' | '?') { 
-            sentence_endings += 1; 
+        if self.artifacts {
+            let lower = trimmed.to_lowercase();
+            if ["foreword", "appendix", "index", "references"].contains(&lower.as_str()) {
+                return Some(FilterRule::Artifact);
+            }
+        }
+        if let Some(max) = self.max_ellipses {
+            let ellipses = trimmed.matches("...").count() + trimmed.matches("[...]").count();
+            if ellipses > max {
+                return Some(FilterRule::MaxEllipses);
+            }
         }
-        if c.is_whitespace() {
-            last_char_was_whitespace = true;
-        } else if last_char_was_whitespace {
-            words += 1;
-            last_char_was_whitespace = false;
+        if self.code && looks_like_code(trimmed) {
+            return Some(FilterRule::Code);
         }
+        None
     }
+}
 
-    // More lenient checks
-    if words < 8 { 
-        return false; 
-    }
-    
-    let alpha_ratio = alpha as f64 / trimmed.len() as f64;
-    if alpha_ratio < 0.5 {
-        return false;
+impl Default for ChunkFilter {
+    fn default() -> Self {
+        Self {
+            min_chars: Some(50),
+            min_words: Some(8),
+            min_alpha_ratio: Some(0.5),
+            max_ellipses: Some(2),
+            artifacts: true,
+            code: true,
+        }
     }
+}
 
-    // Filter single-word artifacts
-    let single_word_artifacts = ["foreword", "appendix", "index", "references"];
-    let lower = trimmed.to_lowercase();
-    if single_word_artifacts.iter().any(|&w| lower == w) {
-        return false;
+/// Parses a comma separated list of the rules to apply, like
+/// `min_chars=20,code`. Rules without a value use their default threshold,
+/// unlisted rules are off and `none` turns all of them off.
+impl FromStr for ChunkFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let defaults = Self::default();
+        let mut filter = Self::none();
+        for rule in s.split(',').map(str::trim) {
+            if rule.is_empty() || rule == "none" {
+                continue;
+            }
+            let (name, value) = match rule.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (rule, None),
+            };
+            let parse_value = |default: Option<usize>| -> Result<Option<usize>> {
+                value.map_or(Ok(default), |value| {
+                    Ok(Some(value.parse().map_err(|_| {
+                        anyhow!("Invalid value '{}' for filter rule '{}'", value, name)
+                    })?))
+                })
+            };
+            match name {
+                "min_chars" => filter.min_chars = parse_value(defaults.min_chars)?,
+                "min_words" => filter.min_words = parse_value(defaults.min_words)?,
+                "max_ellipses" => filter.max_ellipses = parse_value(defaults.max_ellipses)?,
+                "min_alpha_ratio" => {
+                    filter.min_alpha_ratio = match value {
+                        Some(value) => Some(value.parse().map_err(|_| {
+                            anyhow!("Invalid value '{}' for filter rule '{}'", value, name)
+                        })?),
+                        None => defaults.min_alpha_ratio,
+                    }
+                }
+                "artifacts" | "code" if value.is_some() => {
+                    bail!("Filter rule '{}' takes no value", name)
+                }
+                "artifacts" => filter.artifacts = true,
+                "code" => filter.code = true,
+                _ => bail!(
+                    "Unknown filter rule '{}', expected min_chars, min_words, min_alpha_ratio, artifacts, max_ellipses, code or none",
+                    name
+                ),
+            }
+        }
+        Ok(filter)
     }
+}
 
-    // Filter excessive ellipsis
-    let ellipsis_count = trimmed.matches("...").count() + trimmed.matches("[...]").count();
-    if ellipsis_count > 2 { 
-        return false; 
-    }
+/// A chunk left out of the index, and the rule that rejected it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DroppedChunk {
+    pub page: u16,
+    pub end_page: u16,
+    pub rule: FilterRule,
+    pub text: String,
+}
 
-    // Ensure meaningful content
-    !looks_like_code(trimmed)
+/// The chunks the filter dropped while chunking a document.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilterReport {
+    pub dropped: Vec<DroppedChunk>,
+    /// Near-duplicate chunks folded into an earlier one by `dedup`.
+    #[serde(default)]
+    pub collapsed: usize,
 }
 
-// Helper function to detect code-like content
-fn looks_like_code(text: &str) -> bool {
-    let code_indicators = ["{", "}", "function", "var ", "const ", "let ", "=>", "//"];
-    let indicator_count = code_indicators.iter()
-        .filter(|&&indicator| text.contains(indicator))
-        .count();
-    
-    indicator_count >= 3
+impl FilterReport {
+    /// Number of chunks each rule dropped, by rule.
+    pub fn counts(&self) -> BTreeMap<FilterRule, usize> {
+        let mut counts = BTreeMap::new();
+        for chunk in &self.dropped {
+            *counts.entry(chunk.rule).or_insert(0) += 1;
+        }
+        counts
+    }
 }
 
-pub fn extract_and_chunk_(pdf_source: PdfSource) -> Result<Vec<Chunk>> {
-    let doc = match pdf_source {
-        PdfSource::Path(path) => Document::load(path)?,
-        PdfSource::Bytes(vec) => Document::load_mem(&vec)?,
-    };
-    let pages = doc.get_pages();
+/// Chunking settings of a document, recorded so that retrieval quality can
+/// be compared across settings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChunkingConfig {
+    pub method: ChunkingMethod,
+    pub size: usize,
+    /// In the unit of `size`, except for sentence windows, which count
+    /// sentences.
+    pub overlap: usize,
+    pub cleaning: Cleaning,
+    /// Lets text flow across page breaks instead of chunking every page
+    /// on its own.
+    #[serde(default)]
+    pub cross_page: bool,
+    /// Quality rules chunks must pass to be embedded.
+    #[serde(default)]
+    pub filter: ChunkFilter,
+    /// Cuts the text into paragraph sections of up to this many characters
+    /// first, and chunks each section on its own as children of it.
+    #[serde(default)]
+    pub parent_size: Option<usize>,
+    /// Collapses near-duplicate chunks, such as repeated notices, into the
+    /// first of them.
+    #[serde(default)]
+    pub dedup: bool,
+}
 
-    let mut chunks: Vec<Chunk> = Vec::new();
+impl ChunkingConfig {
+    /// `method` with its default size and overlap, and no cleaning.
+    pub fn new(method: ChunkingMethod) -> Self {
+        let (size, overlap) = match method {
+            ChunkingMethod::Characters => (500, 0),
+            ChunkingMethod::Tokens => (128, 0),
+            ChunkingMethod::SentenceWindow => (1000, 2),
+            ChunkingMethod::Paragraph => (1000, 0),
+        };
+        Self {
+            method,
+            size,
+            overlap,
+            cleaning: Cleaning::None,
+            cross_page: false,
+            filter: ChunkFilter::default(),
+            parent_size: None,
+            dedup: false,
+        }
+    }
 
-    // Create splitter - overlap is passed to chunks() method, not in constructor
-    let splitter = TextSplitter::new(500); // chunk size
+    /// Builds the configured strategy. Token sizes are capped at what the
+    /// model embeds, and require its `tokenizer`.
+    pub fn strategy(&self, tokenizer: Option<&ModelTokenizer>) -> Result<Box<dyn ChunkingStrategy>> {
+        Ok(match self.method {
+            ChunkingMethod::Characters => Box::new(CharacterChunker::new(self.size, self.overlap)?),
+            ChunkingMethod::Tokens => {
+                let Some(tokenizer) = tokenizer else {
+                    bail!("Token chunk sizes need an embedding model with a tokenizer");
+                };
+                let size = self.size.min(tokenizer.text_budget()?);
+                Box::new(TokenChunker::new(tokenizer, size, self.overlap)?)
+            }
+            ChunkingMethod::SentenceWindow => {
+                Box::new(SentenceWindowChunker::new(self.size, self.overlap)?)
+            }
+            ChunkingMethod::Paragraph => Box::new(ParagraphChunker::new(self.size, self.overlap)?),
+        })
+    }
 
-    for &page_num in pages.keys() {
-        if let Ok(text) = doc.extract_text(&[page_num]) {
-            let chunk_texts: Vec<_> = splitter.chunks(&text).collect();
+    /// Splits text into the parent sections, when chunking with parents.
+    pub fn parent_strategy(&self) -> Result<Option<ParagraphChunker>> {
+        self.parent_size
+            .map(|size| ParagraphChunker::new(size, 0))
+            .transpose()
+    }
+}
 
-            for chunk in chunk_texts {
-                if is_valid_chunk(&chunk) {
-                    chunks.push(Chunk {
-                        content: chunk.to_string(),
-                        page: (page_num) as u16,
-                    });
-                }
-            }
-        }
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self::new(ChunkingMethod::Characters)
     }
-    Ok(chunks)
 }
 
-fn is_valid_chunk_(text: &str) -> bool {
-    let trimmed = text.trim();
+/// Cuts text into chunks, returned as byte ranges of the text.
+pub trait ChunkingStrategy: Send + Sync {
+    fn split(&self, text: &str) -> Vec<Range<usize>>;
+}
 
-    // 1. Minimum length check (at least 50 characters for meaningful content)
-    if trimmed.len() < 50 {
-        return false;
+fn splitter_config<S: ChunkSizer>(size: usize, overlap: usize, sizer: S) -> Result<ChunkConfig<S>> {
+    if size == 0 {
+        bail!("Chunk size must be positive");
     }
+    ChunkConfig::new(size)
+        .with_sizer(sizer)
+        .with_overlap(overlap)
+        .map_err(|_| anyhow!("Chunk overlap {} must be smaller than the size {}", overlap, size))
+}
 
-    // 2. Must contain at least one complete sentence
-    if !has_complete_sentence(trimmed) {
-        return false;
-    }
+fn splitter_ranges<S: ChunkSizer>(splitter: &TextSplitter<S>, text: &str) -> Vec<Range<usize>> {
+    splitter
+        .chunk_indices(text)
+        .map(|(start, chunk)| start..start + chunk.len())
+        .collect()
+}
 
-    // 3. Filter out code-like patterns
-    if looks_like_code(trimmed) {
-        return false;
-    }
+pub struct CharacterChunker {
+    splitter: TextSplitter<Characters>,
+}
 
-    // 4. Check word count (at least 8 words)
-    let word_count = trimmed.split_whitespace().count();
-    if word_count < 8 {
-        return false;
+impl CharacterChunker {
+    pub fn new(size: usize, overlap: usize) -> Result<Self> {
+        Ok(Self {
+            splitter: TextSplitter::new(splitter_config(size, overlap, Characters)?),
+        })
     }
+}
 
-    // 5. Check for reasonable ratio of alphabetic characters
-    let alpha_count = trimmed.chars().filter(|c| c.is_alphabetic()).count();
-    let alpha_ratio = alpha_count as f32 / trimmed.len() as f32;
-    if alpha_ratio < 0.5 {
-        return false;
+impl ChunkingStrategy for CharacterChunker {
+    fn split(&self, text: &str) -> Vec<Range<usize>> {
+        splitter_ranges(&self.splitter, text)
     }
+}
 
-    // 6. Ensure multiple sentences
-    let sentence_endings = text.matches(&['.', '?', '!'][..]).count();
-    if sentence_endings < 2 {
-        return false;
+pub struct TokenChunker {
+    splitter: TextSplitter<Tokenizer>,
+}
+
+impl TokenChunker {
+    pub fn new(tokenizer: &ModelTokenizer, size: usize, overlap: usize) -> Result<Self> {
+        let config = splitter_config(size, overlap, tokenizer.tokenizer().clone())?;
+        Ok(Self {
+            splitter: TextSplitter::new(config),
+        })
     }
+}
 
-    // 7. Check for common single-word artifacts
-    let single_word_artifacts = ["foreword", "appendix", "index", "references"];
-    if single_word_artifacts
-        .iter()
-        .any(|&word| trimmed.to_lowercase() == word)
-    {
-        return false;
+impl ChunkingStrategy for TokenChunker {
+    fn split(&self, text: &str) -> Vec<Range<usize>> {
+        splitter_ranges(&self.splitter, text)
     }
+}
 
-    // 8. Reject if it's mostly ellipsis or truncation
-    if trimmed.contains("...") || trimmed.contains("[...]") {
-        let ellipsis_count = trimmed.matches("...").count() + trimmed.matches("[...]").count();
-        if ellipsis_count > 2 {
-            return false;
+pub struct SentenceWindowChunker {
+    size: usize,
+    overlap: usize,
+}
+
+impl SentenceWindowChunker {
+    pub fn new(size: usize, overlap: usize) -> Result<Self> {
+        if size == 0 {
+            bail!("Chunk size must be positive");
         }
+        Ok(Self { size, overlap })
     }
+}
 
-    true
+impl ChunkingStrategy for SentenceWindowChunker {
+    fn split(&self, text: &str) -> Vec<Range<usize>> {
+        let mut chunks = Vec::new();
+        let mut window: Vec<Range<usize>> = Vec::new();
+        let mut window_chars = 0;
+        // Sentences not yet part of an emitted chunk
+        let mut fresh = 0;
+
+        for sentence in sentences(text) {
+            window_chars += text[sentence.clone()].chars().count();
+            window.push(sentence);
+            fresh += 1;
+
+            if window_chars >= self.size {
+                chunks.push(trim_range(text, window[0].start..window[window.len() - 1].end));
+                let keep = self.overlap.min(window.len() - 1);
+                window.drain(..window.len() - keep);
+                window_chars = window.iter().map(|s| text[s.clone()].chars().count()).sum();
+                fresh = 0;
+            }
+        }
+        if fresh > 0 {
+            chunks.push(trim_range(text, window[0].start..window[window.len() - 1].end));
+        }
+        chunks
+    }
 }
 
-fn has_complete_sentence(text: &str) -> bool {
-    // Check for sentence-ending punctuation followed by space or end of string
-    let has_period = text.contains(". ") || text.ends_with('.');
-    let has_question = text.contains("? ") || text.ends_with('?');
-    let has_exclamation = text.contains("! ") || text.ends_with('!');
+pub struct ParagraphChunker {
+    size: usize,
+    overlap: usize,
+    /// Splits paragraphs that alone exceed `size`.
+    long: CharacterChunker,
+}
 
-    // Must have at least one sentence ender and start with capital letter
-    (has_period || has_question || has_exclamation)
-        && text.chars().next().map_or(false, |c| c.is_uppercase())
+impl ParagraphChunker {
+    pub fn new(size: usize, overlap: usize) -> Result<Self> {
+        Ok(Self {
+            size,
+            overlap,
+            long: CharacterChunker::new(size, overlap)?,
+        })
+    }
 }
 
-fn looks_like_code_(text: &str) -> bool {
-    // Common code patterns
-    let code_indicators = [
-        "//",
-        "/*",
-        "*/",
-        "*/\n",
-        "{}",
-        "[]",
-        "();",
-        "function ",
-        "const ",
-        "let ",
-        "var ",
-        "def ",
-        "class ",
-        "import ",
-        "```",
-        "fn ",
-        "pub fn",
-    ];
+impl ChunkingStrategy for ParagraphChunker {
+    fn split(&self, text: &str) -> Vec<Range<usize>> {
+        let chars = |range: &Range<usize>| text[range.clone()].chars().count();
+        let span = |paragraphs: &[Range<usize>]| paragraphs[0].start..paragraphs[paragraphs.len() - 1].end;
+
+        let mut chunks = Vec::new();
+        let mut current: Vec<Range<usize>> = Vec::new();
+        let mut current_chars = 0;
+        // Paragraphs carried over from the previous chunk as overlap
+        let mut carried = 0;
+
+        for paragraph in paragraphs(text) {
+            let paragraph_chars = chars(&paragraph);
+            if current_chars + paragraph_chars > self.size && current.len() > carried {
+                chunks.push(span(&current));
+
+                // Repeat the trailing paragraphs that fit in the overlap
+                let mut kept_chars = 0;
+                let keep = current
+                    .iter()
+                    .rev()
+                    .take_while(|p| {
+                        kept_chars += chars(p);
+                        kept_chars <= self.overlap
+                    })
+                    .count();
+                current.drain(..current.len() - keep);
+                current_chars = current.iter().map(chars).sum();
+                carried = current.len();
+            }
 
-    // Check for multiple code indicators
-    let indicator_count = code_indicators
-        .iter()
-        .filter(|&pattern| text.contains(pattern))
-        .count();
+            if paragraph_chars > self.size {
+                if current.len() > carried {
+                    chunks.push(span(&current));
+                }
+                let offset = paragraph.start;
+                chunks.extend(
+                    self.long
+                        .split(&text[paragraph])
+                        .into_iter()
+                        .map(|piece| piece.start + offset..piece.end + offset),
+                );
+                current.clear();
+                current_chars = 0;
+                carried = 0;
+                continue;
+            }
 
-    // If 2+ code indicators, likely code
-    if indicator_count >= 2 {
-        return true;
+            current.push(paragraph);
+            current_chars += paragraph_chars;
+        }
+        if current.len() > carried {
+            chunks.push(span(&current));
+        }
+        chunks
     }
+}
 
-    // Check if mostly punctuation/symbols
-    let symbol_count = text
-        .chars()
-        .filter(|c| matches!(c, '{' | '}' | '[' | ']' | '(' | ')' | ';' | '/' | '*'))
-        .count();
+/// Byte ranges of the non-empty, trimmed blocks of text separated by blank
+/// lines.
+fn paragraphs(text: &str) -> Vec<Range<usize>> {
+    static BLANK_LINE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n\s*\n").unwrap());
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    for separator in BLANK_LINE.find_iter(text) {
+        paragraphs.push(trim_range(text, start..separator.start()));
+        start = separator.end();
+    }
+    paragraphs.push(trim_range(text, start..text.len()));
+    paragraphs.retain(|paragraph| !paragraph.is_empty());
+    paragraphs
+}
 
-    symbol_count > text.len() / 4
+/// Byte ranges of the sentences of `text` that contain any letters or digits.
+fn sentences(text: &str) -> Vec<Range<usize>> {
+    text.split_sentence_bound_indices()
+        .filter(|(_, sentence)| sentence.chars().any(char::is_alphanumeric))
+        .map(|(start, sentence)| start..start + sentence.len())
+        .collect()
 }
 
-pub fn chunk_pages_with_splitter(pages: &[Page], max_chars: usize) -> Vec<Chunk> {
-    let splitter = TextSplitter::new(max_chars);
+/// Shrinks `range` to exclude leading and trailing whitespace.
+fn trim_range(text: &str, range: Range<usize>) -> Range<usize> {
+    let slice = &text[range.clone()];
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    let end = range.end - (slice.len() - slice.trim_end().len());
+    start..end.max(start)
+}
 
-    let mut chunks: Vec<Chunk> = Vec::new();
+/// Text chunked as a whole: one page, or every page in cross-page mode.
+struct Source<'a> {
+    text: String,
+    pages: Vec<SourcePage<'a>>,
+}
 
-    for page in pages {
-        // The splitter returns an iterator of &str chunks
-        let chunk_texts = splitter.chunks(&page.content);
+struct SourcePage<'a> {
+    page: u16,
+    /// Byte range of the page's text in the source text.
+    range: Range<usize>,
+    /// See [`Page::char_indices`]; dropped when cleaning rewrote the text.
+    char_indices: Option<&'a [usize]>,
+}
 
-        for chunk_str in chunk_texts {
-            chunks.push(Chunk {
-                content: chunk_str.to_string(),
-                page: page.page_num + 1,
+impl<'a> Source<'a> {
+    /// Joins the cleaned text of `pages` with line breaks, in page order.
+    fn new(pages: &[&'a Page], cleaning: Cleaning) -> Self {
+        let mut text = String::new();
+        let mut source_pages = Vec::with_capacity(pages.len());
+        for page in pages {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            let start = text.len();
+            text.push_str(&cleaning.apply(&page.content));
+            source_pages.push(SourcePage {
+                page: page.page_num,
+                range: start..text.len(),
+                char_indices: match cleaning {
+                    Cleaning::None => page.char_indices.as_deref(),
+                    _ => None,
+                },
             });
         }
+        Self {
+            text,
+            pages: source_pages,
+        }
     }
 
-    chunks
-}
+    fn page_at(&self, offset: usize) -> u16 {
+        self.pages
+            .iter()
+            .find(|page| offset < page.range.end)
+            .or(self.pages.last())
+            .map_or(0, |page| page.page)
+    }
 
-pub fn chunk_per_page(pages: &[Page]) -> Vec<Chunk> {
-    let mut return_chunks: Vec<Chunk> = Vec::new();
-    for page in pages {
-        let chunks = smart_chunk_text(&page.content, 2000, true);
-        for chunk in chunks {
-            if is_garbage_sentence(&chunk) {
+    fn chunk(&self, range: Range<usize>) -> Chunk {
+        let chars_between = |from: usize, to: usize| self.text[from..to].chars().count();
+        let spans: Vec<PageSpan> = self
+            .pages
+            .iter()
+            .filter_map(|page| {
+                let start = page.range.start.max(range.start);
+                let end = page.range.end.min(range.end);
+                if start >= end {
+                    return None;
+                }
+
+                // First and last char of the span, counted from the page start
+                let first = chars_between(page.range.start, start);
+                let last = chars_between(page.range.start, end) - 1;
+                let pdf_chars = page.char_indices.and_then(|indices| {
+                    Some(*indices.get(first)?..*indices.get(last)? + 1)
+                });
+                Some(PageSpan {
+                    page: page.page,
+                    start: chars_between(range.start, start),
+                    end: chars_between(range.start, end),
+                    pdf_chars,
+                    rects: None,
+                })
+            })
+            .collect();
+
+        let page = spans.first().map_or(self.page_at(range.start), |span| span.page);
+        Chunk {
+            content: self.text[range.clone()].to_string(),
+            page,
+            end_page: spans.last().map_or(page, |span| span.page),
+            spans,
+            parent: None,
+            duplicates: vec![],
+        }
+    }
+
+    /// Splits chunks longer than the model's input limit into pieces that fit.
+    fn fit_to_model(
+        &self,
+        ranges: Vec<Range<usize>>,
+        tokenizer: &ModelTokenizer,
+    ) -> Result<Vec<Range<usize>>> {
+        let config = ChunkConfig::new(tokenizer.text_budget()?).with_sizer(tokenizer.tokenizer());
+        let splitter = TextSplitter::new(config);
+
+        let mut fitted = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let text = &self.text[range.clone()];
+            let tokens = tokenizer.count(text)?;
+            if tokens <= tokenizer.max_tokens() {
+                fitted.push(range);
                 continue;
             }
-            return_chunks.push(Chunk {
-                content: (chunk),
-                page: (page.page_num),
+
+            let pieces = splitter_ranges(&splitter, text);
+            eprintln!(
+                "Chunk on page {} has {} tokens, over the model's limit of {}; split into {} chunks",
+                self.page_at(range.start),
+                tokens,
+                tokenizer.max_tokens(),
+                pieces.len()
+            );
+            fitted.extend(
+                pieces
+                    .into_iter()
+                    .map(|piece| piece.start + range.start..piece.end + range.start),
+            );
+        }
+        Ok(fitted)
+    }
+}
+
+pub fn extract_and_chunk(pdf_source: PdfSource) -> Result<(Vec<Chunk>, FilterReport)> {
+    extract_and_chunk_with_progress(pdf_source, &progress::ignore)
+}
+
+pub fn extract_and_chunk_with_progress(
+    pdf_source: PdfSource,
+    on_progress: ProgressFn,
+) -> Result<(Vec<Chunk>, FilterReport)> {
+    let pages = extract_pages_with_progress(pdf_source, on_progress)?.pages;
+    let (chunks, report) = chunk_pages(&pages, &ChunkingConfig::default(), None)?;
+    on_progress(Progress::ChunksCreated {
+        chunks: chunks.len(),
+    });
+    Ok((chunks, report))
+}
+
+/// Extracts the text of every page with lopdf. Pages whose text cannot be
+/// extracted are skipped.
+pub fn extract_pages(pdf_source: PdfSource) -> Result<Vec<Page>> {
+    Ok(extract_pages_with_progress(pdf_source, &progress::ignore)?.pages)
+}
+
+/// Like [`extract_pages`], reporting `PagesExtracted` after every page.
+pub fn extract_pages_with_progress(
+    pdf_source: PdfSource,
+    on_progress: ProgressFn,
+) -> Result<File> {
+    // Load the PDF
+    let doc = match pdf_source {
+        PdfSource::Path(path) => Document::load(path)?,
+        PdfSource::Bytes(vec) => Document::load_mem(&vec)?,
+    };
+
+    let pages = doc.get_pages();
+
+    // Collect page numbers into a Vec for parallel iteration
+    let page_numbers: Vec<u32> = pages.keys().copied().collect();
+    let total = page_numbers.len();
+    let done = AtomicUsize::new(0);
+
+    // Parallel iteration over page numbers
+    let pages: Vec<Page> = page_numbers
+        .par_iter()
+        .filter_map(|&page_num| {
+            let page = doc.extract_text(&[page_num]).ok().map(|text| Page {
+                content: text,
+                page_num: page_num as u16,
+                char_indices: None,
+                backend: Some(Backend::Lopdf),
             });
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            on_progress(Progress::PagesExtracted { done, total });
+            page
+        })
+        .collect();
+    Ok(File {
+        pages,
+        page_count: total as u16,
+    })
+}
+
+/// Splits extracted pages into chunks as configured. Chunks failing the
+/// configured filter are left out and listed in the report with the rule
+/// that rejected them.
+///
+/// With a `tokenizer`, no chunk exceeds what the model embeds: longer ones
+/// are reported and split rather than left to be truncated.
+pub fn chunk_pages(
+    pages: &[Page],
+    config: &ChunkingConfig,
+    tokenizer: Option<&ModelTokenizer>,
+) -> Result<(Vec<Chunk>, FilterReport)> {
+    let strategy = config.strategy(tokenizer)?;
+
+    let sources: Vec<Source> = if config.cross_page {
+        let mut ordered: Vec<&Page> = pages.iter().collect();
+        ordered.sort_by_key(|page| page.page_num);
+        vec![Source::new(&ordered, config.cleaning)]
+    } else {
+        pages
+            .iter()
+            .map(|page| Source::new(&[page], config.cleaning))
+            .collect()
+    };
+
+    let parents = config.parent_strategy()?;
+
+    let chunked = sources
+        .par_iter()
+        .map(|source| {
+            let sections = match &parents {
+                Some(parents) => parents.split(&source.text),
+                None => std::iter::once(0..source.text.len()).collect(),
+            };
+            let mut families = Vec::new();
+            let mut dropped = Vec::new();
+            for section in sections {
+                let mut kept = Vec::new();
+                let offset = section.start;
+                for child in strategy.split(&source.text[section.clone()]) {
+                    let range = child.start + offset..child.end + offset;
+                    match config.filter.check(&source.text[range.clone()]) {
+                        Some(rule) => {
+                            let chunk = source.chunk(range);
+                            dropped.push(DroppedChunk {
+                                page: chunk.page,
+                                end_page: chunk.end_page,
+                                rule,
+                                text: chunk.content.trim().to_string(),
+                            });
+                        }
+                        None => kept.push(range),
+                    }
+                }
+                let kept = match tokenizer {
+                    Some(tokenizer) => source.fit_to_model(kept, tokenizer)?,
+                    None => kept,
+                };
+                let children: Vec<Chunk> = kept.into_iter().map(|range| source.chunk(range)).collect();
+                let parent = parents.as_ref().map(|_| source.chunk(section));
+                families.push((parent, children));
+            }
+            Ok((families, dropped))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut chunks = Vec::new();
+    let mut report = FilterReport::default();
+    let mut parent_id = 0;
+    for (families, dropped) in chunked {
+        for (parent, mut children) in families {
+            if let Some(parent) = parent {
+                if children.is_empty() {
+                    continue;
+                }
+                let parent = ParentChunk {
+                    id: parent_id,
+                    content: parent.content,
+                    page: parent.page,
+                    end_page: parent.end_page,
+                };
+                parent_id += 1;
+                for child in &mut children {
+                    child.parent = Some(parent.clone());
+                }
+            }
+            chunks.extend(children);
+        }
+        report.dropped.extend(dropped);
+    }
+    if config.dedup {
+        (chunks, report.collapsed) = collapse_duplicates(chunks);
+    }
+    Ok((chunks, report))
+}
+
+/// Keeps the first of every set of near-duplicate chunks, listing where the
+/// others were. Also returns the number of chunks collapsed.
+fn collapse_duplicates(chunks: Vec<Chunk>) -> (Vec<Chunk>, usize) {
+    let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.content.as_str()).collect();
+    let originals = dedup::find_duplicates(&texts);
+    let collapsed = originals.iter().flatten().count();
+
+    let mut kept: Vec<Option<Chunk>> = Vec::with_capacity(chunks.len());
+    for (chunk, original) in chunks.into_iter().zip(originals) {
+        match original.and_then(|i| kept[i].as_mut()) {
+            Some(original) => {
+                original.duplicates.push(ChunkLocation {
+                    page: chunk.page,
+                    end_page: chunk.end_page,
+                });
+                kept.push(None);
+            }
+            None => kept.push(Some(chunk)),
         }
     }
-    return_chunks
+    (kept.into_iter().flatten().collect(), collapsed)
+}
+
+fn looks_like_code(text: &str) -> bool {
+    let code_indicators = ["{", "}", "function", "var ", "const ", "let ", "=>", "//"];
+    let indicator_count = code_indicators
+        .iter()
+        .filter(|&&indicator| text.contains(indicator))
+        .count();
+
+    indicator_count >= 3
 }
 
 pub fn remove_section_headers(text: &str) -> String {
@@ -306,18 +958,23 @@ pub fn remove_section_headers(text: &str) -> String {
     let mut cleaned_lines = Vec::new();
 
     // Regex patterns for common section headers
-    let section_number_regex = Regex::new(
-        r"^[\s]*(\d+\.)+\d*[\s]*[A-Z]", // Matches "3.1 Introduction" or "3.1.2 Details"
-    )
-    .unwrap();
-
-    let chapter_regex =
-        Regex::new(r"^[\s]*(Chapter|Section|Part|Appendix)[\s]+(\d+|[A-Z])").unwrap();
-
-    let simple_header_regex = Regex::new(
-        r"^[\s]*\d+\.[\s]*[A-Z][a-z]+", // Matches "3. Introduction"
-    )
-    .unwrap();
+    static SECTION_NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"^[\s]*(\d+\.)+\d*[\s]*[A-Z]", // Matches "3.1 Introduction" or "3.1.2 Details"
+        )
+        .unwrap()
+    });
+
+    static CHAPTER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^[\s]*(Chapter|Section|Part|Appendix)[\s]+(\d+|[A-Z])").unwrap()
+    });
+
+    static SIMPLE_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"^[\s]*\d+\.[\s]*[A-Z][a-z]+", // Matches "3. Introduction"
+        )
+        .unwrap()
+    });
 
     for line in lines {
         let trimmed = line.trim();
@@ -328,9 +985,9 @@ pub fn remove_section_headers(text: &str) -> String {
         }
 
         // Check if line is a section header
-        let is_header = section_number_regex.is_match(trimmed)
-            || chapter_regex.is_match(trimmed)
-            || simple_header_regex.is_match(trimmed)
+        let is_header = SECTION_NUMBER.is_match(trimmed)
+            || CHAPTER.is_match(trimmed)
+            || SIMPLE_HEADER.is_match(trimmed)
             || is_likely_header(trimmed);
 
         if !is_header {
@@ -351,7 +1008,7 @@ fn is_likely_header(line: &str) -> bool {
     }
 
     // Check for common patterns
-    let starts_with_number = trimmed.chars().next().map_or(false, |c| c.is_numeric());
+    let starts_with_number = trimmed.chars().next().is_some_and(|c| c.is_numeric());
     let has_colon = trimmed.contains(':');
     let word_count = trimmed.split_whitespace().count();
 
@@ -379,12 +1036,16 @@ fn is_likely_header(line: &str) -> bool {
     false
 }
 
-fn split_into_sentences(text: &str) -> Vec<String> {
-    text.unicode_sentences().map(|s| s.to_string()).collect()
-}
-
 /// Clean PDF text for chunking / embeddings.
 pub fn clean_pdf_text_robust(text: &str, remove_headers: bool) -> String {
+    // Compiled once, as this runs for every paragraph
+    static TOC_LEADER: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?m)^[\s\d]*([.]\s*){5,}[\s\d]*$").unwrap());
+    static HYPHEN_LINEBREAK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)-\n").unwrap());
+    static WHITESPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+    static REPEATED_PUNCT: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"([.,!?;:]){3,}").unwrap());
+
     let mut cleaned = text.to_string();
 
     // 1: Optional: remove section headers
@@ -393,8 +1054,7 @@ pub fn clean_pdf_text_robust(text: &str, remove_headers: bool) -> String {
     }
 
     // 2: Remove TOC / leader lines like ". . . 415 . . . 422"
-    let toc_leader_regex = Regex::new(r"(?m)^[\s\d]*([.]\s*){5,}[\s\d]*$").unwrap();
-    cleaned = toc_leader_regex.replace_all(&cleaned, "").to_string();
+    cleaned = TOC_LEADER.replace_all(&cleaned, "").to_string();
 
     // 3: Remove lines that are mostly non-letters
     cleaned = cleaned
@@ -408,8 +1068,7 @@ pub fn clean_pdf_text_robust(text: &str, remove_headers: bool) -> String {
         .join("\n");
 
     // 4: Fix hyphenated line breaks ("rejec-\nted" → "rejected")
-    let hyphen_linebreak_regex = Regex::new(r"(?m)-\n").unwrap();
-    cleaned = hyphen_linebreak_regex.replace_all(&cleaned, "").to_string();
+    cleaned = HYPHEN_LINEBREAK.replace_all(&cleaned, "").to_string();
 
     // 5: Join lines with space (avoid word merges)
     let mut fixed_text = String::new();
@@ -432,8 +1091,7 @@ pub fn clean_pdf_text_robust(text: &str, remove_headers: bool) -> String {
         .collect();
 
     // 7: Normalize whitespace
-    let whitespace_regex = Regex::new(r"\s+").unwrap();
-    cleaned = whitespace_regex.replace_all(&cleaned, " ").to_string();
+    cleaned = WHITESPACE.replace_all(&cleaned, " ").to_string();
 
     // 8: Replace common PDF ligatures
     cleaned = cleaned
@@ -449,116 +1107,11 @@ pub fn clean_pdf_text_robust(text: &str, remove_headers: bool) -> String {
         .replace("\u{00A0}", " ");
 
     // 9: Remove repeated punctuation artifacts
-    let punct_regex = Regex::new(r"([.,!?;:]){3,}").unwrap();
-    cleaned = punct_regex.replace_all(&cleaned, "$1").to_string();
-
-    cleaned.trim().to_string()
-}
-
-pub fn clean_pdf_text_advanced(text: &str, remove_headers: bool) -> String {
-    let mut cleaned = text.to_string();
-
-    // Remove headers first if requested
-    if remove_headers {
-        cleaned = remove_section_headers(&cleaned);
-    }
-
-    let toc_leader_regex = Regex::new(r"(?m)^[\s\d]*([.]\s*){5,}[\s\d]*$").unwrap();
-
-    //remove table of contents leader lines
-    cleaned = toc_leader_regex.replace_all(&cleaned, "").to_string();
-
-    //remove mostly non-letter lines
-    cleaned = cleaned
-        .lines()
-        .filter(|line| {
-            let letters = line.chars().filter(|c| c.is_alphabetic()).count();
-            let total = line.chars().count();
-
-            // Keep line if it has enough real text
-            total == 0 || letters * 4 >= total
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    // Remove common PDF artifacts
-    cleaned = cleaned
-        .chars()
-        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
-        .collect();
-
-    // Normalize whitespace
-    let whitespace_regex = Regex::new(r"\s+").unwrap();
-    cleaned = whitespace_regex.replace_all(&cleaned, " ").to_string();
-
-    // Remove common PDF ligatures and special chars
-    cleaned = cleaned
-        .replace("ﬁ", "fi")
-        .replace("ﬂ", "fl")
-        .replace("ﬀ", "ff")
-        .replace("ﬃ", "ffi")
-        .replace("ﬄ", "ffl")
-        .replace("\u{FEFF}", "")
-        .replace("\u{200B}", "")
-        .replace("\u{00A0}", " ");
-
-    // Remove repeated punctuation artifacts
-    let punct_regex = Regex::new(r"([.,!?;:]){3,}").unwrap();
-    cleaned = punct_regex.replace_all(&cleaned, "$1").to_string();
+    cleaned = REPEATED_PUNCT.replace_all(&cleaned, "$1").to_string();
 
     cleaned.trim().to_string()
 }
 
-fn is_garbage_sentence(s: &str) -> bool {
-    let letters = s.chars().filter(|c| c.is_alphabetic()).count();
-    let digits = s.chars().filter(|c| c.is_numeric()).count();
-    let dots = s.matches('.').count();
-
-    dots > 10 && letters < 5 && digits > 0
-}
-
-// Updated chunking function
-pub fn smart_chunk_text(text: &str, chunk_size: usize, remove_headers: bool) -> Vec<String> {
-    let cleaned = clean_pdf_text_robust(text, remove_headers);
-    let sentences = split_into_sentences(&cleaned);
-
-    let mut chunks = Vec::new();
-    let mut current_chunk = String::new();
-    let mut sentence_buffer = Vec::new();
-
-    for sentence in sentences {
-        // Skip if sentence looks like a standalone header that slipped through
-        if is_likely_header(&sentence) {
-            continue;
-        }
-
-        sentence_buffer.push(sentence.clone());
-        current_chunk.push_str(&sentence);
-        current_chunk.push(' ');
-
-        if current_chunk.len() >= chunk_size {
-            chunks.push(current_chunk.trim().to_string());
-
-            current_chunk = sentence_buffer
-                .iter()
-                .rev()
-                .take(2)
-                .rev()
-                .cloned()
-                .collect::<Vec<_>>()
-                .join(" ");
-
-            sentence_buffer.clear();
-        }
-    }
-
-    if !current_chunk.is_empty() {
-        chunks.push(current_chunk.trim().to_string());
-    }
-
-    chunks
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -574,6 +1127,8 @@ mod tests {
                 explicitly programmed. This chapter introduces basic \
                 terminology and concepts used throughout the book."
                     .repeat(20),
+                char_indices: None,
+                backend: None,
             },
             Page {
                 page_num: 2,
@@ -582,24 +1137,248 @@ mod tests {
                 learning algorithms. Despite its simplicity, it forms \
                 the basis for more complex models."
                     .repeat(20),
+                char_indices: None,
+                backend: None,
             },
         ];
 
-        let chunks = chunk_per_page(&pages);
+        let config = ChunkingConfig {
+            method: ChunkingMethod::SentenceWindow,
+            size: 2000,
+            overlap: 2,
+            cleaning: Cleaning::Headers,
+            cross_page: false,
+            filter: ChunkFilter::default(),
+            parent_size: None,
+            dedup: false,
+        };
+        let (chunks, _) = chunk_pages(&pages, &config, None).unwrap();
+
+        // Each page is longer than one window
+        for page in [1, 2] {
+            assert!(chunks.iter().filter(|chunk| chunk.page == page).count() > 1);
+        }
+        for chunk in &chunks {
+            assert!(chunk.page == 1 || chunk.page == 2);
+            assert!(!chunk.content.trim().is_empty());
+        }
+        // Windows on a page repeat the last sentences of the previous one
+        for pair in chunks.windows(2).filter(|pair| pair[0].page == pair[1].page) {
+            let last_sentence = pair[0].content.trim_end().rsplit(". ").next().unwrap();
+            assert!(pair[1].content.contains(last_sentence));
+        }
+    }
+
+    #[test]
+    fn token_chunks_fit_the_model() {
+        let tokenizer = tokenizers::Tokenizer::from_file("model/tokenizer.json").unwrap();
+        let tokenizer = ModelTokenizer::new(tokenizer);
+        let pages = vec![Page {
+            page_num: 3,
+            content: "The pump draws water from the reservoir through a filter. \
+            Clean the filter monthly to keep the flow rate stable. "
+                .repeat(40),
+            char_indices: None,
+            backend: None,
+        }];
+
+        let tokens = ChunkingConfig {
+            size: 40,
+            ..ChunkingConfig::new(ChunkingMethod::Tokens)
+        };
+        let (chunks, _) = chunk_pages(&pages, &tokens, Some(&tokenizer)).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(tokenizer.count(&chunk.content).unwrap() <= 42);
+        }
 
+        // Character chunks the model would truncate are split as well
+        let characters = ChunkingConfig {
+            size: 4000,
+            ..ChunkingConfig::default()
+        };
+        let (chunks, _) = chunk_pages(&pages, &characters, Some(&tokenizer)).unwrap();
         for chunk in &chunks {
-            print!("chunk start");
-            println!("{:?}", chunk.content);
-            println!("");
+            assert!(tokenizer.count(&chunk.content).unwrap() <= tokenizer.max_tokens());
         }
+        assert!(chunk_pages(&pages, &tokens, None).is_err());
+    }
+
+    #[test]
+    fn paragraph_chunks_keep_paragraphs_whole() {
+        let text = "Install the pump on a level base.\n\n\
+                    Connect the inlet hose.\n  \n\
+                    Prime the pump before first use.";
+        let chunker = ParagraphChunker::new(60, 30).unwrap();
+
+        let chunks: Vec<&str> = chunker.split(text).into_iter().map(|r| &text[r]).collect();
+        assert_eq!(
+            chunks,
+            vec![
+                "Install the pump on a level base.\n\nConnect the inlet hose.",
+                "Connect the inlet hose.\n  \nPrime the pump before first use.",
+            ]
+        );
+        assert!(ParagraphChunker::new(60, 60).is_err());
+    }
+
+    #[test]
+    fn cross_page_chunks_record_both_pages() {
+        let pages = vec![
+            Page {
+                page_num: 2,
+                content: "The impeller must be inspected every six months, and \
+                          worn blades must be replaced before"
+                    .to_string(),
+                char_indices: None,
+                backend: None,
+            },
+            Page {
+                page_num: 1,
+                content: "Maintenance of the pump is described in this chapter in detail.".to_string(),
+                char_indices: None,
+                backend: None,
+            },
+            Page {
+                page_num: 3,
+                content: "the pump is returned to service after any repair.".to_string(),
+                char_indices: None,
+                backend: None,
+            },
+        ];
+        let config = ChunkingConfig {
+            cross_page: true,
+            ..ChunkingConfig::default()
+        };
+
+        let (chunks, _) = chunk_pages(&pages, &config, None).unwrap();
+        assert_eq!(chunks.len(), 1);
+        let chunk = &chunks[0];
+        assert_eq!((chunk.page, chunk.end_page), (1, 3));
+
+        let chars: Vec<char> = chunk.content.chars().collect();
+        let page_text = |span: &PageSpan| chars[span.start..span.end].iter().collect::<String>();
+        assert_eq!(chunk.spans.len(), 3);
+        assert_eq!(page_text(&chunk.spans[0]), pages[1].content);
+        assert!(page_text(&chunk.spans[1]).starts_with("The impeller"));
+        assert_eq!(page_text(&chunk.spans[2]), pages[2].content);
+    }
+
+    #[test]
+    fn chunks_record_pdf_char_ranges() {
+        let content = "  Maintenance of the pump is described in this chapter in detail.\n";
+        // Pretend the page text starts at pdfium char 100
+        let char_indices: Vec<usize> = (100..100 + content.chars().count()).collect();
+        let pages = vec![Page {
+            page_num: 4,
+            content: content.to_string(),
+            char_indices: Some(char_indices),
+            backend: None,
+        }];
+
+        let (chunks, _) = chunk_pages(&pages, &ChunkingConfig::default(), None).unwrap();
+        let span = &chunks[0].spans[0];
+        assert_eq!(span.pdf_chars, Some(102..102 + content.trim().chars().count()));
+
+        let cleaned = ChunkingConfig {
+            cleaning: Cleaning::Text,
+            ..ChunkingConfig::default()
+        };
+        let (chunks, _) = chunk_pages(&pages, &cleaned, None).unwrap();
+        assert_eq!(chunks[0].spans[0].pdf_chars, None);
+    }
+
+    #[test]
+    fn filter_reports_dropped_chunks_and_rules_can_be_turned_off() {
+        let prose = "The pump must be serviced every six months by a trained technician.";
+        let code = "function start() { const pump = init(); pump.run(); // go\n}";
+        let pages = vec![
+            Page {
+                page_num: 1,
+                content: prose.to_string(),
+                char_indices: None,
+                backend: None,
+            },
+            Page {
+                page_num: 2,
+                content: code.to_string(),
+                char_indices: None,
+                backend: None,
+            },
+        ];
 
-        // sanity checks
-        assert!(!chunks.is_empty());
+        let (chunks, report) = chunk_pages(&pages, &ChunkingConfig::default(), None).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].page, 2);
+        assert_eq!(report.dropped[0].rule, FilterRule::Code);
+
+        let keep_code = ChunkingConfig {
+            filter: "min_chars,min_words".parse().unwrap(),
+            ..ChunkingConfig::default()
+        };
+        let (chunks, report) = chunk_pages(&pages, &keep_code, None).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(report.dropped.is_empty());
+
+        let filter: ChunkFilter = "min_chars=10,code".parse().unwrap();
+        assert_eq!(filter.min_chars, Some(10));
+        assert_eq!(filter.min_words, None);
+        assert!(filter.code);
+        assert_eq!("none".parse::<ChunkFilter>().unwrap(), ChunkFilter::none());
+        assert!("min_words=few".parse::<ChunkFilter>().is_err());
+    }
 
-        // every chunk should be associated with a valid page
+    #[test]
+    fn child_chunks_link_to_their_parent_section() {
+        let first = "The pump draws water through the inlet and pushes it out through the outlet. ".repeat(3);
+        let second = "Clean the filter every month, and replace it when it is damaged or clogged. ".repeat(3);
+        let pages = vec![Page {
+            page_num: 1,
+            content: format!("{}\n\n{}", first.trim(), second.trim()),
+            char_indices: None,
+            backend: None,
+        }];
+        let config = ChunkingConfig {
+            size: 100,
+            overlap: 0,
+            parent_size: Some(300),
+            ..ChunkingConfig::new(ChunkingMethod::SentenceWindow)
+        };
+
+        let (chunks, _) = chunk_pages(&pages, &config, None).unwrap();
+        assert!(chunks.len() > 2);
         for chunk in &chunks {
-            assert!(chunk.page == 1 || chunk.page == 2);
-            assert!(!chunk.content.is_empty());
+            let parent = chunk.parent.as_ref().unwrap();
+            assert!(parent.content.contains(chunk.content.trim()));
         }
+        let parent_ids: Vec<usize> = chunks.iter().map(|c| c.parent.as_ref().unwrap().id).collect();
+        assert_eq!(parent_ids.first(), Some(&0));
+        assert_eq!(parent_ids.last(), Some(&1));
+        assert!(chunks[0].parent.as_ref().unwrap().content.starts_with("The pump"));
+    }
+
+    #[test]
+    fn dedup_collapses_repeated_notices_into_one_chunk() {
+        let notice = "Copyright 2024 Acme Pumps. All rights reserved. Do not reproduce this page.";
+        let pages: Vec<Page> = (1..=3)
+            .map(|page_num| Page {
+                page_num,
+                content: notice.to_string(),
+                char_indices: None,
+                backend: None,
+            })
+            .collect();
+        let config = ChunkingConfig {
+            dedup: true,
+            ..ChunkingConfig::default()
+        };
+
+        let (chunks, report) = chunk_pages(&pages, &config, None).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(report.collapsed, 2);
+        assert_eq!(chunks[0].page, 1);
+        let pages: Vec<u16> = chunks[0].duplicates.iter().map(|location| location.page).collect();
+        assert_eq!(pages, vec![2, 3]);
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+/// Fingerprints of near-duplicates differ in at most this many bits.
+const MAX_DISTANCE: u32 = 3;
+
+/// Words per shingle; shorter texts are fingerprinted by their words.
+const SHINGLE_WORDS: usize = 3;
+
+/// 64-bit SimHash over word shingles of `text`, ignoring case, punctuation
+/// and whitespace. Similar texts get fingerprints differing in few bits.
+pub fn simhash(text: &str) -> u64 {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_WORDS.min(words.len()).max(1)) {
+        let hash = fnv1a(shingle.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// Stable across builds, unlike the standard library's hasher.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn is_near_duplicate(a: u64, b: u64) -> bool {
+    (a ^ b).count_ones() <= MAX_DISTANCE
+}
+
+/// For every text, the index of the earlier text it nearly duplicates, or
+/// `None` for the first of its kind.
+pub fn find_duplicates(texts: &[&str]) -> Vec<Option<usize>> {
+    // Fingerprints within MAX_DISTANCE bits agree on at least one of
+    // MAX_DISTANCE + 1 bands, so only texts sharing a band are compared
+    const BANDS: u32 = MAX_DISTANCE + 1;
+    let band_bits = 64 / BANDS;
+    let band = |hash: u64, i: u32| (i, (hash >> (i * band_bits)) & ((1 << band_bits) - 1));
+
+    let mut originals: HashMap<(u32, u64), Vec<(usize, u64)>> = HashMap::new();
+    texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let hash = simhash(text);
+            let original = (0..BANDS)
+                .filter_map(|b| originals.get(&band(hash, b)))
+                .flatten()
+                .find(|(_, other)| is_near_duplicate(hash, *other))
+                .map(|(j, _)| *j);
+            if original.is_none() {
+                for b in 0..BANDS {
+                    originals.entry(band(hash, b)).or_default().push((i, hash));
+                }
+            }
+            original
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_boilerplate_repeated_with_small_changes() {
+        let notice = "No part of this manual may be reproduced or transmitted in any form \
+                      without the prior written permission of the publisher.";
+        let texts = [
+            notice,
+            "The pump must be serviced every six months by a trained technician.",
+            &notice.replace("publisher.", "publisher"),
+            &notice.to_uppercase(),
+            "Clean the filter every month and replace it when it is damaged.",
+        ];
+
+        assert_eq!(
+            find_duplicates(&texts),
+            vec![None, None, Some(0), Some(0), None]
+        );
+    }
+}
@@ -1,83 +1,624 @@
-use anyhow::Error;
+use anyhow::{Error, anyhow};
 use fastembed::{
-    EmbeddingModel, InitOptionsUserDefined, ModelTrait, QuantizationMode, TextEmbedding,
+    InitOptionsUserDefined, QuantizationMode, TextEmbedding, TextInitOptions,
     TokenizerFiles, UserDefinedEmbeddingModel,
 };
-use once_cell::sync::OnceCell;
 use std::fs;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use tokenizers::Tokenizer;
 
 use crate::chunk::Chunk;
+use crate::lexical;
+use crate::progress::{self, Progress, ProgressFn};
+
+pub use fastembed::EmbeddingModel;
 
 pub struct Embeddings {
     pub original: Vec<Chunk>,
     pub embedded: Vec<Vec<f32>>,
 }
 
-static MODEL_CELL: OnceCell<Arc<RwLock<TextEmbedding>>> = OnceCell::new();
+/// Turns text into vectors compared by dot product.
+pub trait Embedder: Send + Sync {
+    /// Identifies the model, so vectors from different models are never mixed.
+    fn model_id(&self) -> &str;
+
+    fn dim(&self) -> usize;
+
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error>;
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, Error> {
+        self.embed_documents(&[query])?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedder returned no vector for the query"))
+    }
+
+    /// The tokenizer documents are run through, for models that have one.
+    fn tokenizer(&self) -> Option<&ModelTokenizer> {
+        None
+    }
+}
+
+/// A model's tokenizer, with the number of tokens a document may have
+/// before the model truncates it.
+#[derive(Clone)]
+pub struct ModelTokenizer {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+}
+
+impl ModelTokenizer {
+    /// Takes the limit from the tokenizer's truncation setting, which is
+    /// where fastembed applies the model's maximum input length.
+    pub fn new(mut tokenizer: Tokenizer) -> Self {
+        let max_tokens = tokenizer
+            .get_truncation()
+            .map_or(usize::MAX, |truncation| truncation.max_length);
+        // Counting must see the whole text, unpadded
+        tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(None)
+            .expect("disabling truncation cannot fail");
+        Self {
+            tokenizer,
+            max_tokens,
+        }
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    /// Most tokens a document may have, special tokens included.
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Tokens the model sees for `text`, special tokens included.
+    pub fn count(&self, text: &str) -> Result<usize, Error> {
+        Ok(self
+            .tokenizer
+            .encode_fast(text, true)
+            .map_err(|e| anyhow!("Failed to tokenize text: {}", e))?
+            .len())
+    }
+
+    /// Most tokens of document text that fit, leaving room for the special
+    /// tokens the tokenizer adds.
+    pub fn text_budget(&self) -> Result<usize, Error> {
+        Ok(self.max_tokens.saturating_sub(self.count("")?))
+    }
+
+    /// Lowers the limit by the tokens of `prefix`, prepended to every document.
+    fn reserve(mut self, prefix: &str) -> Result<Self, Error> {
+        if !prefix.is_empty() {
+            let prefix_tokens = self.count(prefix)? - self.count("")?;
+            self.max_tokens = self.max_tokens.saturating_sub(prefix_tokens);
+        }
+        Ok(self)
+    }
+}
+
+/// Text prepended to queries and passages. Instruction-tuned models such as
+/// e5 and bge are trained with these and lose accuracy without them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prompts {
+    pub query: String,
+    pub passage: String,
+}
+
+impl Prompts {
+    /// The prompts a model expects, going by its published model card.
+    pub fn for_model(model_id: &str) -> Self {
+        let name = model_id.rsplit('/').next().unwrap_or(model_id).to_lowercase();
+        let (query, passage) = if name.contains("e5-") {
+            ("query: ", "passage: ")
+        } else if name.starts_with("bge-") && name.contains("-zh-") {
+            ("为这个句子生成表示以用于检索相关文章：", "")
+        } else if name.starts_with("bge-") || name.starts_with("mxbai-embed-large") {
+            ("Represent this sentence for searching relevant passages: ", "")
+        } else if name.starts_with("nomic-embed-text") {
+            ("search_query: ", "search_document: ")
+        } else {
+            ("", "")
+        };
+
+        Self {
+            query: query.to_string(),
+            passage: passage.to_string(),
+        }
+    }
+}
+
+/// [`Embedder`] applying [`Prompts`] before handing texts to the inner model.
+pub struct PromptedEmbedder {
+    inner: Arc<dyn Embedder>,
+    prompts: Prompts,
+    /// The inner tokenizer, less the room taken by the passage prompt.
+    tokenizer: Option<ModelTokenizer>,
+}
+
+impl PromptedEmbedder {
+    pub fn new(inner: Arc<dyn Embedder>, prompts: Prompts) -> Result<Self, Error> {
+        let tokenizer = inner
+            .tokenizer()
+            .map(|tokenizer| tokenizer.clone().reserve(&prompts.passage))
+            .transpose()?;
+        Ok(Self {
+            inner,
+            prompts,
+            tokenizer,
+        })
+    }
+}
+
+impl Embedder for PromptedEmbedder {
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
 
-fn initialize_model() -> Result<TextEmbedding, Error> {
-    let model_dir = "model";
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
 
-    let onnx_file = fs::read(format!("{}/model_qint8_arm64.onnx", model_dir))?;
-    let tokenizer_file = fs::read(format!("{}/tokenizer.json", model_dir))?;
-    let config_file = fs::read(format!("{}/config.json", model_dir))?;
-    let special_tokens = fs::read(format!("{}/special_tokens_map.json", model_dir))?;
-    let tokenizer_config = fs::read(format!("{}/tokenizer_config.json", model_dir))?;
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        if self.prompts.passage.is_empty() {
+            return self.inner.embed_documents(texts);
+        }
+        let prompted: Vec<String> = texts
+            .iter()
+            .map(|text| format!("{}{}", self.prompts.passage, text))
+            .collect();
+        let prompted: Vec<&str> = prompted.iter().map(String::as_str).collect();
+        self.inner.embed_documents(&prompted)
+    }
 
-    let model_data = UserDefinedEmbeddingModel {
-        onnx_file,
-        tokenizer_files: TokenizerFiles {
-            tokenizer_file,
-            config_file,
-            special_tokens_map_file: special_tokens,
-            tokenizer_config_file: tokenizer_config,
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, Error> {
+        self.inner
+            .embed_query(&format!("{}{}", self.prompts.query, query))
+    }
+
+    fn tokenizer(&self) -> Option<&ModelTokenizer> {
+        self.tokenizer.as_ref()
+    }
+}
+
+/// Which [`Embedder`] to load.
+#[derive(Debug, Clone)]
+pub enum EmbedderConfig {
+    /// One of fastembed's built-in models, downloaded on first use.
+    Fastembed(EmbeddingModel),
+    /// An ONNX model plus tokenizer files in a local directory.
+    OnnxDir(PathBuf),
+    /// Deterministic feature hashing, for tests and offline development.
+    Hashing { dim: usize },
+}
+
+impl Default for EmbedderConfig {
+    fn default() -> Self {
+        Self::OnnxDir(PathBuf::from("model"))
+    }
+}
+
+/// Model used when an ONNX directory holds no model file usable on this host.
+const FALLBACK_MODEL: EmbeddingModel = EmbeddingModel::AllMiniLML6V2;
+
+/// Loads the configured embedder. Model backends get `sessions` model
+/// instances for documents, so that many texts are embedded concurrently,
+/// plus one reserved for queries.
+pub fn load(config: &EmbedderConfig, sessions: usize) -> Result<Arc<dyn Embedder>, Error> {
+    Ok(match config {
+        EmbedderConfig::Fastembed(model) => {
+            Arc::new(FastembedEmbedder::new(model.clone(), sessions)?)
+        }
+        EmbedderConfig::OnnxDir(dir) => match find_onnx_model(dir) {
+            Ok(onnx_path) => Arc::new(FastembedEmbedder::from_files(&onnx_path, dir, sessions)?),
+            Err(e) => {
+                eprintln!("{}; falling back to {:?} from the fastembed cache", e, FALLBACK_MODEL);
+                Arc::new(FastembedEmbedder::new(FALLBACK_MODEL, sessions).map_err(|fallback| {
+                    anyhow!("{}. Loading {:?} also failed: {}", e, FALLBACK_MODEL, fallback)
+                })?)
+            }
         },
-        output_key: None,
-        pooling: None,
-        quantization: QuantizationMode::None,
-    };
+        EmbedderConfig::Hashing { dim } => Arc::new(HashingEmbedder::new(*dim)),
+    })
+}
+
+/// ONNX file names to try for this host, most specific first. Quantized
+/// exports are only usable on the instruction set they were built for, so
+/// the portable fp32 `model.onnx` always comes last.
+fn onnx_candidates() -> Vec<&'static str> {
+    let mut names = Vec::new();
+
+    #[cfg(target_arch = "aarch64")]
+    names.push("model_qint8_arm64.onnx");
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512vnni") {
+            names.push("model_qint8_avx512_vnni.onnx");
+        }
+        if is_x86_feature_detected!("avx512f") {
+            names.push("model_qint8_avx512.onnx");
+        }
+        if is_x86_feature_detected!("avx2") {
+            names.push("model_quint8_avx2.onnx");
+        }
+    }
 
-    TextEmbedding::try_new_from_user_defined(model_data, InitOptionsUserDefined::default())
+    names.push("model_quantized.onnx");
+    names.push("model.onnx");
+    names
 }
 
-pub fn get_embeddings(original: Vec<Chunk>) -> Result<Embeddings, Error> {
-    // Initialize model on first call
-    let model = MODEL_CELL.get_or_try_init(|| {
-        let result = initialize_model();
-        result.map(|m| Arc::new(RwLock::new(m)))
-    })?;
+/// Finds the best ONNX model for this host in `dir` or its `onnx/`
+/// subdirectory (the Hugging Face export layout).
+pub fn find_onnx_model(dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+    let dir = dir.as_ref();
+    let searched: Vec<PathBuf> = onnx_candidates()
+        .into_iter()
+        .flat_map(|name| [dir.join(name), dir.join("onnx").join(name)])
+        .collect();
+
+    searched
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            let searched: Vec<String> = searched.iter().map(|p| p.display().to_string()).collect();
+            anyhow!(
+                "No ONNX model for {} found, searched: {}",
+                std::env::consts::ARCH,
+                searched.join(", ")
+            )
+        })
+}
+
+/// Id of the model exported to `dir`: the name in its `config.json`, or
+/// else the directory name. Not the `.onnx` path, which varies with the
+/// host's variant and how the directory is spelled, while the collection
+/// and the embedding cache must keep matching.
+fn onnx_model_id(dir: &Path, config_file: &[u8]) -> String {
+    serde_json::from_slice::<serde_json::Value>(config_file)
+        .ok()
+        .and_then(|config| config.get("_name_or_path")?.as_str().map(str::to_string))
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| {
+            let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+            Some(dir.file_name()?.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "onnx".to_string())
+}
+
+/// Fixed set of model instances, each used by one caller at a time.
+/// `TextEmbedding::embed` needs `&mut self`, so a session cannot be shared.
+struct SessionPool<S = TextEmbedding> {
+    idle: Mutex<Vec<S>>,
+    returned: Condvar,
+}
+
+/// A session taken from the pool, put back when dropped, including when the
+/// caller panics, so waiting callers are never left without one.
+struct PooledSession<'a, S> {
+    pool: &'a SessionPool<S>,
+    session: Option<S>,
+}
+
+impl<S> Drop for PooledSession<'_, S> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.pool.idle.lock().unwrap().push(session);
+            self.pool.returned.notify_one();
+        }
+    }
+}
 
+impl<S> SessionPool<S> {
+    fn new(sessions: Vec<S>) -> Self {
+        Self {
+            idle: Mutex::new(sessions),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Runs `f` on an idle session, waiting for one if all are busy.
+    fn with<T>(&self, f: impl FnOnce(&mut S) -> T) -> T {
+        let session = {
+            let mut idle = self.idle.lock().unwrap();
+            loop {
+                match idle.pop() {
+                    Some(session) => break session,
+                    None => idle = self.returned.wait(idle).unwrap(),
+                }
+            }
+        };
+
+        let mut pooled = PooledSession {
+            pool: self,
+            session: Some(session),
+        };
+        f(pooled.session.as_mut().unwrap())
+    }
+}
+
+/// [`Embedder`] running an ONNX model through fastembed.
+///
+/// Queries get a session of their own, so search stays responsive while
+/// documents are being ingested.
+pub struct FastembedEmbedder {
+    documents: SessionPool,
+    queries: SessionPool,
+    tokenizer: ModelTokenizer,
+    model_id: String,
+    dim: usize,
+}
+
+impl FastembedEmbedder {
+    pub fn new(model: EmbeddingModel, sessions: usize) -> Result<Self, Error> {
+        let info = TextEmbedding::get_model_info(&model)?;
+        let model_id = info.model_code.clone();
+        let dim = info.dim;
+
+        let mut instances = (0..=sessions.max(1))
+            .map(|_| TextEmbedding::try_new(TextInitOptions::new(model.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let query_session = instances.pop().unwrap();
+        let tokenizer = ModelTokenizer::new(query_session.tokenizer.clone());
+
+        Ok(Self {
+            documents: SessionPool::new(instances),
+            queries: SessionPool::new(vec![query_session]),
+            tokenizer,
+            model_id,
+            dim,
+        })
+    }
+
+    /// Loads the ONNX model at `onnx_path` with the tokenizer files in
+    /// `tokenizer_dir`. The dimension is read off a probe embedding, since
+    /// the files carry no model metadata fastembed understands.
+    pub fn from_files(
+        onnx_path: &Path,
+        tokenizer_dir: impl AsRef<Path>,
+        sessions: usize,
+    ) -> Result<Self, Error> {
+        let dir = tokenizer_dir.as_ref();
+
+        let onnx_file = fs::read(onnx_path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", onnx_path.display(), e))?;
+        let tokenizer_file = fs::read(dir.join("tokenizer.json"))?;
+        let config_file = fs::read(dir.join("config.json"))?;
+        let special_tokens = fs::read(dir.join("special_tokens_map.json"))?;
+        let tokenizer_config = fs::read(dir.join("tokenizer_config.json"))?;
+
+        let model_data = UserDefinedEmbeddingModel {
+            onnx_file,
+            tokenizer_files: TokenizerFiles {
+                tokenizer_file,
+                config_file,
+                special_tokens_map_file: special_tokens,
+                tokenizer_config_file: tokenizer_config,
+            },
+            output_key: None,
+            pooling: None,
+            quantization: QuantizationMode::None,
+        };
+
+        let mut instances = (0..=sessions.max(1))
+            .map(|_| {
+                TextEmbedding::try_new_from_user_defined(
+                    model_data.clone(),
+                    InitOptionsUserDefined::default(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut query_session = instances.pop().unwrap();
+
+        let dim = query_session
+            .embed(["dimension probe"], None)?
+            .first()
+            .map(Vec::len)
+            .ok_or_else(|| anyhow!("{} produced no embedding", onnx_path.display()))?;
+        let tokenizer = ModelTokenizer::new(query_session.tokenizer.clone());
+
+        Ok(Self {
+            documents: SessionPool::new(instances),
+            queries: SessionPool::new(vec![query_session]),
+            tokenizer,
+            model_id: onnx_model_id(dir, &model_data.tokenizer_files.config_file),
+            dim,
+        })
+    }
+}
+
+impl Embedder for FastembedEmbedder {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        self.documents
+            .with(|session| session.embed(texts, Some(BATCH_SIZE)))
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, Error> {
+        self.queries
+            .with(|session| session.embed([query], None))?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedder returned no vector for the query"))
+    }
+
+    fn tokenizer(&self) -> Option<&ModelTokenizer> {
+        Some(&self.tokenizer)
+    }
+}
+
+/// [`Embedder`] hashing each token into one of `dim` buckets. Shares no
+/// semantics with real models, but is fast, deterministic and needs no
+/// model files.
+pub struct HashingEmbedder {
+    dim: usize,
+    model_id: String,
+}
+
+impl HashingEmbedder {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            model_id: format!("hashing-{}", dim),
+        }
+    }
+
+    fn embed(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dim];
+        for token in lexical::tokenize(text) {
+            let hash = fnv1a(token.as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dim as u64) as usize] += sign;
+        }
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        Ok(texts.iter().map(|text| self.embed(text)).collect())
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const BATCH_SIZE: usize = 32;
+
+/// Embeds `query` on tokio's blocking pool, so model inference never stalls
+/// the async runtime.
+pub async fn embed_query_async(embedder: &Arc<dyn Embedder>, query: &str) -> Result<Vec<f32>, Error> {
+    let embedder = embedder.clone();
+    let query = query.to_string();
+    tokio::task::spawn_blocking(move || embedder.embed_query(&query)).await?
+}
+
+pub fn get_embeddings(embedder: &dyn Embedder, original: Vec<Chunk>) -> Result<Embeddings, Error> {
+    get_embeddings_with_progress(embedder, original, &progress::ignore)
+}
+
+/// Like [`get_embeddings`], reporting an `EmbeddingBatch` after every batch.
+pub fn get_embeddings_with_progress(
+    embedder: &dyn Embedder,
+    original: Vec<Chunk>,
+    on_progress: ProgressFn,
+) -> Result<Embeddings, Error> {
     // Prepare text data
     let contents: Vec<&str> = original
         .iter()
         .map(|chunk| chunk.content.as_str())
         .collect();
 
-    // Generate embeddings (needs write lock for &mut self)
-    let mut model_guard = model.write().unwrap();
-    let embedded = model_guard.embed(contents, Some(32 as usize))?;
-    drop(model_guard); // Explicit drop for clarity
+    let batches = contents.len().div_ceil(BATCH_SIZE);
+    let mut embedded = Vec::with_capacity(contents.len());
+
+    for (batch, texts) in contents.chunks(BATCH_SIZE).enumerate() {
+        embedded.extend(embedder.embed_documents(texts)?);
+
+        on_progress(Progress::EmbeddingBatch {
+            batch: batch + 1,
+            batches,
+            embedded: embedded.len(),
+        });
+    }
 
     Ok(Embeddings { original, embedded })
 }
 
-pub fn embed_query(query: &str) -> Result<Vec<f32>, Error> {
-    let model = MODEL_CELL.get_or_try_init(|| {
-        let result = initialize_model();
-        result.map(|m| Arc::new(RwLock::new(m)))
-    })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Generate embedding for the single query
-    let mut model_guard = model.write().unwrap();
-    let embedded = model_guard.embed(vec![query], None)?;
-    drop(model_guard);
+    #[test]
+    fn onnx_model_ids_do_not_depend_on_the_file_path() {
+        let config = br#"{"_name_or_path": "intfloat/multilingual-e5-small"}"#;
+        assert_eq!(
+            onnx_model_id(Path::new("models/e5/"), config),
+            "intfloat/multilingual-e5-small"
+        );
+        assert_eq!(Prompts::for_model(&onnx_model_id(Path::new("x"), config)).query, "query: ");
 
-    // Return the first (and only) embedding
-    Ok(embedded.into_iter().next().unwrap())
-}
+        assert_eq!(onnx_model_id(Path::new("models/bge-small-en-v1.5"), b"{}"), "bge-small-en-v1.5");
+        assert_eq!(
+            onnx_model_id(Path::new("models/bge-small-en-v1.5/"), b"{}"),
+            "bge-small-en-v1.5"
+        );
+    }
+
+    #[test]
+    fn hashing_embedder_is_deterministic_and_normalized() {
+        let embedder = HashingEmbedder::new(64);
+        let a = embedder.embed_query("Replace filter AB-1234").unwrap();
+        let b = embedder.embed_query("Replace filter AB-1234").unwrap();
+
+        assert_eq!(a.len(), 64);
+        assert_eq!(a, b);
+        let norm: f32 = a.iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pooled_sessions_serve_one_caller_at_a_time() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let pool = SessionPool::new(vec![0usize, 0]);
+        let (busy, most_busy) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    pool.with(|uses| {
+                        let now = busy.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_busy.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        *uses += 1;
+                        busy.fetch_sub(1, Ordering::SeqCst);
+                    })
+                });
+            }
+        });
+
+        assert!(most_busy.into_inner() <= 2);
+        let uses = pool.idle.into_inner().unwrap();
+        assert_eq!(uses.len(), 2);
+        assert_eq!(uses.iter().sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn sessions_return_to_the_pool_when_the_caller_panics() {
+        let pool = SessionPool::new(vec!["session"]);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.with(|_| panic!("embedding failed"))
+        }));
+        assert!(panicked.is_err());
 
-pub fn get_dim() -> usize {
-    let model_info = EmbeddingModel::get_model_info(&EmbeddingModel::AllMiniLML6V2);
-    model_info.expect("Model info should always exist").dim
+        // Would wait forever if the session had been lost
+        assert_eq!(pool.with(|session| *session), "session");
+    }
 }
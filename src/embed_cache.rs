@@ -0,0 +1,379 @@
+use anyhow::{Error, anyhow};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::embed::{Embedder, ModelTokenizer};
+use crate::lru::{CacheStats, LruCache};
+
+struct Entry {
+    bytes: u64,
+    /// Nanoseconds since the Unix epoch; file mtime until first used.
+    last_used: u128,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    /// (last use, key) of every entry, oldest first.
+    order: BTreeSet<(u128, String)>,
+    total_bytes: u64,
+}
+
+impl Index {
+    fn insert(&mut self, key: &str, entry: Entry) {
+        self.total_bytes += entry.bytes;
+        self.order.insert((entry.last_used, key.to_string()));
+        if let Some(previous) = self.entries.insert(key.to_string(), entry) {
+            self.total_bytes -= previous.bytes;
+            self.order.remove(&(previous.last_used, key.to_string()));
+        }
+    }
+
+    /// Marks `key` as used now; false if it is not cached.
+    fn touch(&mut self, key: &str) -> bool {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return false;
+        };
+        let mut used = (entry.last_used, key.to_string());
+        self.order.remove(&used);
+        entry.last_used = now();
+        used.0 = entry.last_used;
+        self.order.insert(used);
+        true
+    }
+
+    /// Removes least recently used entries until at most `max_bytes` are
+    /// left, returning their keys.
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_bytes > max_bytes {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.total_bytes -= entry.bytes;
+            }
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// On-disk store of embedding vectors, one file per vector, evicting the
+/// least recently used entries once `max_bytes` is exceeded.
+pub struct EmbeddingCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<Index>,
+}
+
+impl EmbeddingCache {
+    /// Opens the cache in `dir`, creating it if needed, and indexes the
+    /// vectors already stored there.
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut index = Index::default();
+        for shard in fs::read_dir(&dir)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(shard.path())? {
+                let file = file?;
+                let path = file.path();
+                let (Some(key), Some("f32")) = (
+                    path.file_stem().and_then(|s| s.to_str()),
+                    path.extension().and_then(|s| s.to_str()),
+                ) else {
+                    continue;
+                };
+                let metadata = file.metadata()?;
+                let last_used = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_nanos());
+                index.insert(
+                    key,
+                    Entry {
+                        bytes: metadata.len(),
+                        last_used,
+                    },
+                );
+            }
+        }
+
+        let evicted = index.evict(max_bytes);
+        let cache = Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+        };
+        cache.remove_files(evicted)?;
+        Ok(cache)
+    }
+
+    /// Cache key for `text` embedded by `model_id`. Whitespace is collapsed
+    /// first, so re-chunking that only shifts line breaks still hits.
+    pub fn key(model_id: &str, text: &str) -> String {
+        let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut hasher = Sha256::new();
+        hasher.update(model_id.as_bytes());
+        hasher.update([0]);
+        hasher.update(normalized.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.f32", key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<f32>> {
+        if !self.index.lock().unwrap().touch(key) {
+            return None;
+        }
+
+        let bytes = fs::read(self.path(key)).ok()?;
+        if bytes.len() % 4 != 0 {
+            return None;
+        }
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        )
+    }
+
+    pub fn insert(&self, key: &str, vector: &[f32]) -> Result<(), Error> {
+        let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &bytes)?;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(
+                key,
+                Entry {
+                    bytes: bytes.len() as u64,
+                    last_used: now(),
+                },
+            );
+            index.evict(self.max_bytes)
+        };
+        self.remove_files(evicted)
+    }
+
+    /// Deletes the files of evicted entries; done without holding the index
+    /// lock.
+    fn remove_files(&self, evicted: Vec<String>) -> Result<(), Error> {
+        for key in evicted {
+            let path = self.path(&key);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos())
+}
+
+/// [`Embedder`] that only sends texts missing from the cache to the inner
+/// model. Queries are passed through; see [`QueryCachedEmbedder`].
+pub struct CachedEmbedder {
+    inner: Arc<dyn Embedder>,
+    cache: EmbeddingCache,
+}
+
+impl CachedEmbedder {
+    pub fn new(inner: Arc<dyn Embedder>, cache: EmbeddingCache) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl Embedder for CachedEmbedder {
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
+
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        let keys: Vec<String> = texts
+            .iter()
+            .map(|text| EmbeddingCache::key(self.model_id(), text))
+            .collect();
+        let mut vectors: Vec<Option<Vec<f32>>> = keys
+            .iter()
+            .map(|key| self.cache.get(key).filter(|v| v.len() == self.dim()))
+            .collect();
+
+        let missing: Vec<usize> = (0..texts.len()).filter(|&i| vectors[i].is_none()).collect();
+        if !missing.is_empty() {
+            let missing_texts: Vec<&str> = missing.iter().map(|&i| texts[i]).collect();
+            let embedded = self.inner.embed_documents(&missing_texts)?;
+            for (i, vector) in missing.into_iter().zip(embedded) {
+                if let Err(e) = self.cache.insert(&keys[i], &vector) {
+                    eprintln!("Failed to cache embedding: {:?}", e);
+                }
+                vectors[i] = Some(vector);
+            }
+        }
+
+        vectors
+            .into_iter()
+            .map(|v| v.ok_or_else(|| anyhow!("Embedder returned too few vectors")))
+            .collect()
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, Error> {
+        self.inner.embed_query(query)
+    }
+
+    fn tokenizer(&self) -> Option<&ModelTokenizer> {
+        self.inner.tokenizer()
+    }
+}
+
+/// [`Embedder`] remembering the embeddings of recent queries in memory, so
+/// repeated and backspaced search-as-you-type queries skip the model.
+pub struct QueryCachedEmbedder {
+    inner: Arc<dyn Embedder>,
+    queries: Mutex<LruCache<String, Vec<f32>>>,
+}
+
+impl QueryCachedEmbedder {
+    pub fn new(inner: Arc<dyn Embedder>, capacity: usize) -> Self {
+        Self {
+            inner,
+            queries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.queries.lock().unwrap().stats()
+    }
+}
+
+impl Embedder for QueryCachedEmbedder {
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
+
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        self.inner.embed_documents(texts)
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, Error> {
+        let key = query.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(vector) = self.queries.lock().unwrap().get(&key) {
+            return Ok(vector);
+        }
+
+        // Not holding the lock while embedding, so concurrent misses may
+        // both compute the same vector
+        let vector = self.inner.embed_query(query)?;
+        self.queries.lock().unwrap().insert(key, vector.clone());
+        Ok(vector)
+    }
+
+    fn tokenizer(&self) -> Option<&ModelTokenizer> {
+        self.inner.tokenizer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::HashingEmbedder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingEmbedder {
+        inner: HashingEmbedder,
+        embedded: AtomicUsize,
+    }
+
+    impl Embedder for CountingEmbedder {
+        fn model_id(&self) -> &str {
+            self.inner.model_id()
+        }
+
+        fn dim(&self) -> usize {
+            self.inner.dim()
+        }
+
+        fn embed_documents(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+            self.embedded.fetch_add(texts.len(), Ordering::SeqCst);
+            self.inner.embed_documents(texts)
+        }
+    }
+
+    #[test]
+    fn repeated_texts_are_served_from_cache_within_size_limit() {
+        let dir = std::env::temp_dir().join(format!("vb-embed-cache-{}", uuid::Uuid::new_v4()));
+        let counting = Arc::new(CountingEmbedder {
+            inner: HashingEmbedder::new(8),
+            embedded: AtomicUsize::new(0),
+        });
+        // Room for two 8-dimensional vectors
+        let cache = EmbeddingCache::open(&dir, 64).unwrap();
+        let embedder = CachedEmbedder::new(counting.clone(), cache);
+
+        let first = embedder.embed_documents(&["pump", "filter"]).unwrap();
+        let second = embedder.embed_documents(&["pump  ", "filter"]).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(counting.embedded.load(Ordering::SeqCst), 2);
+
+        embedder.embed_documents(&["valve"]).unwrap();
+        let reopened = EmbeddingCache::open(&dir, 64).unwrap();
+        assert_eq!(reopened.index.lock().unwrap().entries.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn eviction_removes_the_least_recently_used_first() {
+        let mut index = Index::default();
+        for (key, last_used) in [("a", 1), ("b", 2), ("c", 3)] {
+            index.insert(
+                key,
+                Entry {
+                    bytes: 10,
+                    last_used,
+                },
+            );
+        }
+        assert!(index.touch("a"));
+        index.insert(
+            "c",
+            Entry {
+                bytes: 20,
+                last_used: 4,
+            },
+        );
+
+        assert_eq!(index.evict(30), vec!["b".to_string()]);
+        assert_eq!(index.total_bytes, 30);
+        assert_eq!(index.order.len(), 2);
+    }
+}
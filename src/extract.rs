@@ -1,84 +1,286 @@
+use anyhow::{anyhow, bail};
 use pdf_oxide::PdfDocument;
-use rayon::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Deserialize)]
-struct PageDTO {
-    page: u16,
-    text: String,
-}
+use crate::chunk::{self, PdfSource};
+use crate::pdfium::PdfiumExtractor;
+use crate::progress::{Progress, ProgressFn};
 
 #[derive(Debug)]
 pub struct Page {
     pub content: String,
     pub page_num: u16,
+    /// Index in the pdfium text page of every char of `content`, when the
+    /// page was extracted with pdfium.
+    pub char_indices: Option<Vec<usize>>,
+    /// Library the text came from; unknown for text not read from a PDF.
+    pub backend: Option<Backend>,
 }
 pub struct File {
-    pages: Vec<Page>,
+    pub pages: Vec<Page>,
+    /// Pages in the document, including those whose text could not be read.
+    pub page_count: u16,
 }
 
-#[derive(Deserialize)]
-struct PythonOutput {
-    pages: Vec<PageDTO>,
+/// PDF library used to extract text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Pdfium,
+    PdfOxide,
+    Lopdf,
 }
 
-pub fn extract_pdf_file(pdf_path: &str) -> File {
-    let output = std::process::Command::new("python3")
-        .arg("extract_pdf.py")
-        .arg(pdf_path)
-        .output()
-        .expect("Failed to run Python script");
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pdfium => "pdfium",
+            Self::PdfOxide => "pdf_oxide",
+            Self::Lopdf => "lopdf",
+        }
+    }
 
-    if !output.status.success() {
-        let err = String::from_utf8_lossy(&output.stderr);
-        panic!("Python error: {}", err);
+    pub fn extractor(self) -> Box<dyn Extractor> {
+        match self {
+            Self::Pdfium => Box::new(PdfiumExtractor),
+            Self::PdfOxide => Box::new(PdfOxideExtractor),
+            Self::Lopdf => Box::new(LopdfExtractor),
+        }
     }
+}
 
-    let stdout = String::from_utf8(output.stdout).expect("Invalid UTF-8 from Python");
+impl FromStr for Backend {
+    type Err = anyhow::Error;
 
-    println!("{:?}", stdout);
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.trim() {
+            "pdfium" => Self::Pdfium,
+            "pdf_oxide" => Self::PdfOxide,
+            "lopdf" => Self::Lopdf,
+            _ => bail!("Unknown PDF backend '{}', expected pdfium, pdf_oxide or lopdf", s),
+        })
+    }
+}
 
-    let parsed: PythonOutput = serde_json::from_str(&stdout).expect("Failed to parse Python JSON");
+/// Extracts page text with one PDF library.
+pub trait Extractor: Send + Sync {
+    fn backend(&self) -> Backend;
 
-    let pages = parsed
-        .pages
-        .into_iter()
-        .map(|p| Page {
-            page_num: p.page,
-            content: p.text,
-        })
-        .collect();
+    /// Every page whose text could be read, numbered from 1 and tagged with
+    /// [`Extractor::backend`]. Char positions are only known to pdfium.
+    fn extract(&self, bytes: &[u8], on_progress: ProgressFn) -> anyhow::Result<File>;
+}
+
+/// [`Extractor`] using lopdf; see [`chunk::extract_pages`].
+pub struct LopdfExtractor;
 
-    File { pages }
+impl Extractor for LopdfExtractor {
+    fn backend(&self) -> Backend {
+        Backend::Lopdf
+    }
+
+    fn extract(&self, bytes: &[u8], on_progress: ProgressFn) -> anyhow::Result<File> {
+        chunk::extract_pages_with_progress(PdfSource::Bytes(bytes.to_vec()), on_progress)
+    }
 }
 
-pub fn extract_text(file: &str) -> File {
-    let page_count = PdfDocument::open(file).unwrap().page_count().unwrap();
+/// [`Extractor`] using pdf_oxide; see [`extract_pdf_file`].
+pub struct PdfOxideExtractor;
 
-    // Calculate optimal chunk size based on available threads
-    let num_threads = rayon::current_num_threads();
-    let chunk_size = (page_count / num_threads).max(1);
+impl Extractor for PdfOxideExtractor {
+    fn backend(&self) -> Backend {
+        Backend::PdfOxide
+    }
 
-    // Process pages in parallel chunks
-    let pages: Vec<Page> = (0..page_count)
-        .collect::<Vec<_>>()
-        .par_chunks(chunk_size)
-        .flat_map(|chunk| {
-            // Open document once per chunk
-            let mut doc = PdfDocument::open(file).unwrap();
-            chunk
-                .iter()
-                .filter_map(|&page_num| {
-                    doc.extract_text(page_num).ok().map(|text| Page {
-                        content: text,
-                        page_num: page_num as u16,
-                    })
-                })
-                .collect::<Vec<Page>>()
+    fn extract(&self, bytes: &[u8], _on_progress: ProgressFn) -> anyhow::Result<File> {
+        // pdf_oxide only opens files
+        let path = std::env::temp_dir().join(format!("vb-extract-{}.pdf", uuid::Uuid::new_v4()));
+        let file = match std::fs::write(&path, bytes) {
+            Ok(()) => extract_pdf_file(&path.to_string_lossy()).map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        // Removed whether or not extraction worked
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                eprintln!("Failed to remove {}: {}", path.display(), e)
+            }
+            _ => {}
+        }
+        file
+    }
+}
+
+/// Whether extracted text reads like text: not empty, at least a quarter
+/// letters and few undecodable chars. Broken font encodings tend to come
+/// out as symbols or private use chars instead.
+pub fn is_usable_text(text: &str) -> bool {
+    let (mut total, mut letters, mut undecodable) = (0, 0, 0);
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        total += 1;
+        if c.is_alphabetic() {
+            letters += 1;
+        }
+        if c == '\u{FFFD}' || ('\u{E000}'..='\u{F8FF}').contains(&c) {
+            undecodable += 1;
+        }
+    }
+    total > 0 && letters * 4 >= total && undecodable * 10 <= total
+}
+
+/// Extracts pages with the first backend, retrying pages that are missing
+/// or whose text is not [usable](is_usable_text) with the next ones. Blank
+/// pages are common (scans, figures, separators) and alone cause no retry.
+pub struct FallbackExtractor {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl FallbackExtractor {
+    pub fn new(backends: &[Backend]) -> Self {
+        Self {
+            extractors: backends.iter().map(|backend| backend.extractor()).collect(),
+        }
+    }
+
+    /// Pages in order, each tagged with the backend whose text was kept.
+    /// Retries report `PagesRetried` instead of `PagesExtracted`. Fails only
+    /// when no backend could read the document.
+    pub fn extract(&self, bytes: &[u8], on_progress: ProgressFn) -> anyhow::Result<Vec<Page>> {
+        let mut pages: BTreeMap<u16, Page> = BTreeMap::new();
+        let mut page_count = 0;
+        let mut last_error = None;
+
+        for (i, extractor) in self.extractors.iter().enumerate() {
+            let retry = pages_to_retry(&pages, page_count);
+            let read = !pages.is_empty() || page_count > 0;
+            if read && retry.is_empty() {
+                break;
+            }
+            if i > 0 && !read {
+                eprintln!("Retrying the document with {}", extractor.backend().name());
+            } else if i > 0 {
+                eprintln!("Retrying pages {:?} with {}", retry, extractor.backend().name());
+            }
+
+            let report_retry = |progress| match progress {
+                Progress::PagesExtracted { done, total } => {
+                    on_progress(Progress::PagesRetried { done, total })
+                }
+                progress => on_progress(progress),
+            };
+            let progress: ProgressFn = if read { &report_retry } else { on_progress };
+            let extracted = match extractor.extract(bytes, progress) {
+                Ok(extracted) => extracted,
+                Err(e) => {
+                    eprintln!("{} failed: {:?}", extractor.backend().name(), e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            page_count = page_count.max(extracted.page_count);
+            for page in extracted.pages {
+                let replace = match pages.get(&page.page_num) {
+                    Some(kept) => !is_usable_text(&kept.content) && is_usable_text(&page.content),
+                    None => true,
+                };
+                if replace {
+                    pages.insert(page.page_num, page);
+                }
+            }
+        }
+
+        if pages.is_empty() {
+            return Err(last_error.unwrap_or_else(|| anyhow!("No PDF backend is configured")));
+        }
+        Ok(pages.into_values().collect())
+    }
+}
+
+/// Pages of the document that are missing, or have text that is not blank
+/// but not usable either.
+fn pages_to_retry(pages: &BTreeMap<u16, Page>, page_count: u16) -> Vec<u16> {
+    let last = pages.keys().next_back().copied().unwrap_or(0).max(page_count);
+    (1..=last)
+        .filter(|page| match pages.get(page) {
+            Some(page) => !page.content.trim().is_empty() && !is_usable_text(&page.content),
+            None => true,
         })
-        .collect();
+        .collect()
+}
+
+/// Why [`extract_pdf_file`] could not extract a document.
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The file is missing or not a readable PDF.
+    Open {
+        path: String,
+        source: pdf_oxide::Error,
+    },
+    /// The page tree could not be read.
+    PageCount(pdf_oxide::Error),
+    /// No page could be extracted; the error of the first one.
+    Page { page: u16, source: pdf_oxide::Error },
+}
 
-    File { pages: pages }
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open { path, source } => write!(f, "Failed to open PDF {}: {}", path, source),
+            Self::PageCount(source) => write!(f, "Failed to count pages: {}", source),
+            Self::Page { page, source } => {
+                write!(f, "Failed to extract text of page {}: {}", page, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Open { source, .. } | Self::PageCount(source) | Self::Page { source, .. } => {
+                Some(source)
+            }
+        }
+    }
+}
+
+/// Extracts the text of every page with pdf_oxide, numbering pages from 1.
+/// Pages whose text cannot be extracted are skipped, unless that is all of
+/// them.
+pub fn extract_pdf_file(pdf_path: &str) -> Result<File, ExtractError> {
+    let mut doc = PdfDocument::open(pdf_path).map_err(|source| ExtractError::Open {
+        path: pdf_path.to_string(),
+        source,
+    })?;
+    let page_count = doc.page_count().map_err(ExtractError::PageCount)?;
+
+    let mut pages = Vec::with_capacity(page_count);
+    let mut first_error = None;
+    for index in 0..page_count {
+        let page = (index + 1) as u16;
+        match doc.extract_text(index) {
+            Ok(content) => pages.push(Page {
+                content,
+                page_num: page,
+                char_indices: None,
+                backend: Some(Backend::PdfOxide),
+            }),
+            Err(source) => {
+                eprintln!("Failed to extract text of page {}: {}", page, source);
+                first_error.get_or_insert(ExtractError::Page { page, source });
+            }
+        }
+    }
+
+    match first_error {
+        Some(error) if pages.is_empty() => Err(error),
+        _ => Ok(File {
+            pages,
+            page_count: page_count as u16,
+        }),
+    }
 }
 
 impl File {
@@ -86,3 +288,147 @@ impl File {
         &self.pages
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress;
+
+    /// Returns fixed page texts of a document with `.1` pages.
+    struct Fixed(Backend, u16, Vec<(u16, &'static str)>);
+
+    impl Extractor for Fixed {
+        fn backend(&self) -> Backend {
+            self.0
+        }
+
+        fn extract(&self, _bytes: &[u8], on_progress: ProgressFn) -> anyhow::Result<File> {
+            let total = self.2.len();
+            let pages = self
+                .2
+                .iter()
+                .enumerate()
+                .map(|(i, &(page_num, content))| {
+                    on_progress(Progress::PagesExtracted { done: i + 1, total });
+                    Page {
+                        content: content.to_string(),
+                        page_num,
+                        char_indices: None,
+                        backend: Some(self.0),
+                    }
+                })
+                .collect();
+            Ok(File {
+                pages,
+                page_count: self.1,
+            })
+        }
+    }
+
+    fn extracted(pages: &[Page]) -> Vec<(u16, &str, Option<Backend>)> {
+        pages
+            .iter()
+            .map(|page| (page.page_num, page.content.as_str(), page.backend))
+            .collect()
+    }
+
+    #[test]
+    fn fallback_retries_garbled_and_missing_pages() {
+        let garbled = "\u{E012}\u{E047}\u{E003} \u{E019}\u{E044}";
+        let extractor = FallbackExtractor {
+            extractors: vec![
+                Box::new(Fixed(
+                    Backend::Pdfium,
+                    6,
+                    vec![(1, "Pump manual"), (2, garbled), (4, "  "), (5, "Index")],
+                )),
+                Box::new(Fixed(
+                    Backend::Lopdf,
+                    6,
+                    vec![
+                        (1, "Pump"),
+                        (2, "Safety"),
+                        (3, "Setup"),
+                        (4, "Care"),
+                        (5, ""),
+                        (6, "Notes"),
+                    ],
+                )),
+            ],
+        };
+        let events = std::sync::Mutex::new(Vec::new());
+
+        let pages = extractor
+            .extract(b"", &|progress| events.lock().unwrap().push(progress))
+            .unwrap();
+        assert_eq!(
+            extracted(&pages),
+            vec![
+                (1, "Pump manual", Some(Backend::Pdfium)),
+                (2, "Safety", Some(Backend::Lopdf)),
+                (3, "Setup", Some(Backend::Lopdf)),
+                (4, "Care", Some(Backend::Lopdf)),
+                (5, "Index", Some(Backend::Pdfium)),
+                (6, "Notes", Some(Backend::Lopdf)),
+            ]
+        );
+        let events = events.into_inner().unwrap();
+        assert_eq!(events[3], Progress::PagesExtracted { done: 4, total: 4 });
+        assert_eq!(events[9], Progress::PagesRetried { done: 6, total: 6 });
+    }
+
+    #[test]
+    fn fallback_keeps_blank_pages_without_retrying() {
+        let extractor = FallbackExtractor {
+            extractors: vec![
+                Box::new(Fixed(
+                    Backend::Pdfium,
+                    3,
+                    vec![(1, "Pump manual"), (2, ""), (3, " \n")],
+                )),
+                Box::new(Fixed(Backend::Lopdf, 3, vec![(2, "Figure 1")])),
+            ],
+        };
+
+        let pages = extractor.extract(b"", &progress::ignore).unwrap();
+        assert_eq!(
+            extracted(&pages),
+            vec![
+                (1, "Pump manual", Some(Backend::Pdfium)),
+                (2, "", Some(Backend::Pdfium)),
+                (3, " \n", Some(Backend::Pdfium)),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_files_are_reported_not_panicked_on() {
+        let error = extract_pdf_file("does-not-exist.pdf").err().unwrap();
+        assert!(matches!(error, ExtractError::Open { .. }));
+        assert!(error.to_string().contains("does-not-exist.pdf"));
+    }
+
+    #[test]
+    fn pdf_oxide_removes_its_temp_file_when_extraction_fails() {
+        let temp_files = || {
+            std::fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("vb-extract-"))
+                .count()
+        };
+        let before = temp_files();
+
+        assert!(PdfOxideExtractor.extract(b"not a pdf", &progress::ignore).is_err());
+        assert_eq!(temp_files(), before);
+    }
+
+    #[test]
+    fn unreadable_documents_fail_in_pdfium_without_panicking() {
+        // Fails to load, or to bind when the library is missing
+        assert!(PdfiumExtractor.extract(b"not a pdf", &progress::ignore).is_err());
+        let mut chunks = vec![crate::chunk::Chunk::on_page("text", 1)];
+        crate::pdfium::precompute_highlights(b"not a pdf", &mut chunks);
+        assert!(chunks[0].spans.iter().all(|span| span.rects.is_none()));
+    }
+}
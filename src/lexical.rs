@@ -0,0 +1,376 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::dedup;
+use crate::store::{ChunkPayload, Record, ScoredPoint};
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Constant from the original reciprocal rank fusion paper; damps the
+/// advantage of the very top ranks.
+const RRF_K: f32 = 60.0;
+
+/// A keyword match; its payload lives in the vector store under `id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexicalHit {
+    pub id: String,
+    pub score: f32,
+}
+
+/// Only ids and term statistics are kept, so that payload changes never
+/// invalidate saved indexes.
+#[derive(Default, Serialize, Deserialize)]
+struct DocumentIndex {
+    /// Point ids in the vector store.
+    ids: Vec<String>,
+    /// Token lengths of the points, by position in `ids`.
+    lengths: Vec<u32>,
+    /// Term -> (position in `ids`, term frequency).
+    postings: HashMap<String, Vec<(u32, u32)>>,
+}
+
+impl DocumentIndex {
+    fn build(points: &[Record]) -> Self {
+        let mut ids = Vec::with_capacity(points.len());
+        let mut lengths = Vec::with_capacity(points.len());
+        let mut postings: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+
+        for (i, point) in points.iter().enumerate() {
+            let tokens = tokenize(&point.payload.text);
+            ids.push(point.id.clone());
+            lengths.push(tokens.len() as u32);
+
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            for token in tokens {
+                *counts.entry(token).or_default() += 1;
+            }
+            for (term, tf) in counts {
+                postings.entry(term).or_default().push((i as u32, tf));
+            }
+        }
+
+        Self {
+            ids,
+            lengths,
+            postings,
+        }
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Vec<LexicalHit> {
+        let n = self.ids.len() as f32;
+        let avg_len = self.lengths.iter().sum::<u32>() as f32 / n.max(1.0);
+
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                continue;
+            };
+            let df = postings.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for &(i, tf) in postings {
+                let tf = tf as f32;
+                let len = self.lengths[i as usize] as f32;
+                let norm = tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                *scores.entry(i).or_default() += idf * norm;
+            }
+        }
+
+        let mut ranked: Vec<(u32, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(i, score)| LexicalHit {
+                id: self.ids[i as usize].clone(),
+                score,
+            })
+            .collect()
+    }
+}
+
+/// BM25 keyword index over stored chunks, one index per document.
+///
+/// Complements the vector store for exact terms such as part numbers and
+/// error codes, which embeddings tend to blur. Entries use the same point ids
+/// as the vector store so results from both can be fused.
+///
+/// When opened with a directory, every document's index is saved to its own
+/// file there, so indexing one document never rewrites the others.
+pub struct LexicalIndex {
+    documents: RwLock<HashMap<String, DocumentIndex>>,
+    dir: Option<PathBuf>,
+}
+
+impl LexicalIndex {
+    pub fn new() -> Self {
+        Self {
+            documents: RwLock::new(HashMap::new()),
+            dir: None,
+        }
+    }
+
+    /// Loads the indexes saved in `dir`. Unreadable ones are skipped and
+    /// need to be indexed again, see [`LexicalIndex::contains`].
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut documents = HashMap::new();
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "bin") {
+                    continue;
+                }
+                match bincode::deserialize::<(String, DocumentIndex)>(&fs::read(&path)?) {
+                    Ok((filename, index)) => {
+                        documents.insert(filename, index);
+                    }
+                    Err(e) => eprintln!(
+                        "Ignoring unreadable keyword index {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+
+        Ok(Self {
+            documents: RwLock::new(documents),
+            dir: Some(dir),
+        })
+    }
+
+    fn document_path(&self, filename: &str) -> Option<PathBuf> {
+        // Filenames come from uploads and may not be valid paths
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{:016x}.bin", dedup::fnv1a(filename.as_bytes()))))
+    }
+
+    pub fn contains(&self, filename: &str) -> bool {
+        self.documents.read().unwrap().contains_key(filename)
+    }
+
+    /// Indexes the stored points of one document, replacing any previous
+    /// index under the same filename.
+    pub async fn index_document(&self, filename: &str, points: &[Record]) -> Result<()> {
+        let index = DocumentIndex::build(points);
+        if let Some(path) = self.document_path(filename) {
+            let bytes = bincode::serialize(&(filename, &index))?;
+            // Write to a sibling file first so a crash never leaves a torn index
+            let tmp = path.with_extension("tmp");
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::write(&tmp, bytes).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+        }
+
+        self.documents
+            .write()
+            .unwrap()
+            .insert(filename.to_string(), index);
+        Ok(())
+    }
+
+    pub async fn remove_document(&self, filename: &str) -> Result<()> {
+        let removed = self.documents.write().unwrap().remove(filename).is_some();
+        if let Some(path) = self.document_path(filename).filter(|_| removed) {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Best BM25 matches for `query` within one document.
+    pub fn search(&self, filename: &str, query: &str, limit: usize) -> Vec<LexicalHit> {
+        self.documents
+            .read()
+            .unwrap()
+            .get(filename)
+            .map(|index| index.search(query, limit))
+            .unwrap_or_default()
+    }
+}
+
+impl Default for LexicalIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits text into lowercase terms. Identifiers like `AB-1234` are kept
+/// whole and additionally split into their alphanumeric parts, so both
+/// `ab-1234` and `1234` match them.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split_whitespace() {
+        let word = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+
+        let parts: Vec<&str> = word
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect();
+        if parts.len() > 1 {
+            tokens.extend(parts.into_iter().map(str::to_string));
+        }
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// A fused hit with the scores it got from each retriever.
+#[derive(Debug, Clone)]
+pub struct FusedPoint {
+    pub id: String,
+    /// Weighted reciprocal rank fusion score, used for ordering.
+    pub score: f32,
+    pub vector_score: Option<f32>,
+    pub lexical_score: Option<f32>,
+    pub payload: ChunkPayload,
+}
+
+/// Merges two ranked lists with weighted reciprocal rank fusion.
+/// `semantic_weight` is in `[0, 1]`; the lexical list gets the remainder.
+pub fn reciprocal_rank_fusion(
+    semantic: Vec<ScoredPoint>,
+    lexical: Vec<ScoredPoint>,
+    semantic_weight: f32,
+) -> Vec<FusedPoint> {
+    let semantic_weight = semantic_weight.clamp(0.0, 1.0);
+    let lexical_weight = 1.0 - semantic_weight;
+
+    let mut fused: Vec<FusedPoint> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for (rank, point) in semantic.into_iter().enumerate() {
+        positions.insert(point.id.clone(), fused.len());
+        fused.push(FusedPoint {
+            id: point.id,
+            score: semantic_weight / (RRF_K + rank as f32 + 1.0),
+            vector_score: Some(point.score),
+            lexical_score: None,
+            payload: point.payload,
+        });
+    }
+
+    for (rank, point) in lexical.into_iter().enumerate() {
+        let contribution = lexical_weight / (RRF_K + rank as f32 + 1.0);
+        match positions.get(&point.id) {
+            Some(&i) => {
+                fused[i].score += contribution;
+                fused[i].lexical_score = Some(point.score);
+            }
+            None => fused.push(FusedPoint {
+                id: point.id,
+                score: contribution,
+                vector_score: None,
+                lexical_score: Some(point.score),
+                payload: point.payload,
+            }),
+        }
+    }
+
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, text: &str) -> Record {
+        Record {
+            id: id.to_string(),
+            payload: ChunkPayload {
+                filename: "manual.pdf".to_string(),
+                text: text.to_string(),
+                page: 1,
+                spans: vec![],
+                parent: None,
+                duplicates: vec![],
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn exact_identifiers_rank_first_and_fuse_with_vector_hits() {
+        let index = LexicalIndex::new();
+        index
+            .index_document(
+                "manual.pdf",
+                &[
+                    record("a", "Replace the filter every six months."),
+                    record("b", "Error E-4021 means the filter (part AB-1234) is clogged."),
+                    record("c", "The pump is rated for continuous use."),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let lexical: Vec<ScoredPoint> = index
+            .search("manual.pdf", "AB-1234", 5)
+            .into_iter()
+            .map(|hit| ScoredPoint {
+                payload: record(&hit.id, "").payload,
+                id: hit.id,
+                score: hit.score,
+            })
+            .collect();
+        assert_eq!(lexical[0].id, "b");
+        assert!(index.search("manual.pdf", "4021", 5).iter().any(|p| p.id == "b"));
+
+        let semantic = vec![
+            ScoredPoint {
+                id: "a".to_string(),
+                score: 0.8,
+                payload: record("a", "").payload,
+            },
+            ScoredPoint {
+                id: "b".to_string(),
+                score: 0.7,
+                payload: record("b", "").payload,
+            },
+        ];
+        let fused = reciprocal_rank_fusion(semantic, lexical, 0.5);
+        assert_eq!(fused[0].id, "b");
+        assert_eq!(fused[0].vector_score, Some(0.7));
+        assert!(fused[0].lexical_score.is_some());
+    }
+
+    #[tokio::test]
+    async fn saved_indexes_reopen_and_unreadable_ones_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("vb-keywords-{}", uuid::Uuid::new_v4()));
+
+        let index = LexicalIndex::open(&dir).unwrap();
+        for filename in ["manual.pdf", "guide/v2.pdf"] {
+            index
+                .index_document(filename, &[record("a", "Error E-4021")])
+                .await
+                .unwrap();
+        }
+        index.remove_document("guide/v2.pdf").await.unwrap();
+        fs::write(dir.join("old.bin"), b"not an index").unwrap();
+
+        let reopened = LexicalIndex::open(&dir).unwrap();
+        assert!(reopened.contains("manual.pdf"));
+        assert!(!reopened.contains("guide/v2.pdf"));
+        assert_eq!(reopened.search("manual.pdf", "e-4021", 5)[0].id, "a");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
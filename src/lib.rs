@@ -1,5 +1,15 @@
 pub mod chunk;
+pub mod dedup;
 pub mod embed;
+pub mod embed_cache;
 pub mod extract;
 pub mod fuzzy;
+pub mod lexical;
+pub mod lru;
+pub mod memory_store;
+pub mod pdfium;
+pub mod progress;
 pub mod qdrant;
+pub mod registry;
+pub mod rerank;
+pub mod store;
@@ -0,0 +1,360 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::store::{
+    CollectionMetadata, PointFilter, PointGroup, Record, ScoredPoint, StoredPoint, VectorStore,
+};
+
+#[derive(Default, Serialize, Deserialize)]
+struct Collection {
+    dim: usize,
+    metadata: CollectionMetadata,
+    points: Vec<StoredPoint>,
+    /// Point id -> position in `points`; rebuilt on load.
+    #[serde(skip)]
+    positions: HashMap<String, usize>,
+}
+
+impl Collection {
+    fn index_positions(&mut self) {
+        self.positions = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (point.id.clone(), i))
+            .collect();
+    }
+}
+
+/// In-process [`VectorStore`] doing brute-force dot-product search.
+///
+/// When opened with a path, the contents survive restarts: mutations are
+/// written back to disk on [`VectorStore::flush`], which the `store`
+/// functions call once per stored or deleted document.
+pub struct MemoryStore {
+    collections: RwLock<HashMap<String, Collection>>,
+    path: Option<PathBuf>,
+    /// Set by mutations not yet flushed.
+    dirty: AtomicBool,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            collections: RwLock::new(HashMap::new()),
+            path: None,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut collections: HashMap<String, Collection> = if path.exists() {
+            bincode::deserialize(&fs::read(&path)?).map_err(|e| {
+                anyhow!(
+                    "Unreadable memory store snapshot {} ({}); it may come from an older version, delete it to start fresh",
+                    path.display(),
+                    e
+                )
+            })?
+        } else {
+            HashMap::new()
+        };
+        for collection in collections.values_mut() {
+            collection.index_positions();
+        }
+
+        Ok(Self {
+            collections: RwLock::new(collections),
+            path: Some(path),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn changed(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[async_trait]
+impl VectorStore for MemoryStore {
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dim: usize,
+        metadata: &CollectionMetadata,
+    ) -> Result<()> {
+        let mut collections = self.collections.write().unwrap();
+        if collections.contains_key(collection) {
+            return Ok(());
+        }
+        collections.insert(
+            collection.to_string(),
+            Collection {
+                dim,
+                metadata: metadata.clone(),
+                points: Vec::new(),
+                positions: HashMap::new(),
+            },
+        );
+        self.changed();
+        Ok(())
+    }
+
+    async fn collection_metadata(&self, collection: &str) -> Result<Option<CollectionMetadata>> {
+        let collections = self.collections.read().unwrap();
+        Ok(collections.get(collection).map(|c| c.metadata.clone()))
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        let mut collections = self.collections.write().unwrap();
+        if collections.remove(collection).is_some() {
+            self.changed();
+        }
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        Ok(self.collections.read().unwrap().keys().cloned().collect())
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<StoredPoint>) -> Result<()> {
+        let mut collections = self.collections.write().unwrap();
+        let target = collections
+            .get_mut(collection)
+            .ok_or_else(|| anyhow!("Collection not found: {}", collection))?;
+
+        // Check the whole batch first, so a bad point leaves nothing half written
+        if let Some(point) = points.iter().find(|p| p.vector.len() != target.dim) {
+            return Err(anyhow!(
+                "Vector dimension {} does not match collection dimension {}",
+                point.vector.len(),
+                target.dim
+            ));
+        }
+
+        for point in points {
+            match target.positions.get(&point.id) {
+                Some(&i) => target.points[i] = point,
+                None => {
+                    target
+                        .positions
+                        .insert(point.id.clone(), target.points.len());
+                    target.points.push(point);
+                }
+            }
+        }
+
+        self.changed();
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredPoint>> {
+        let collections = self.collections.read().unwrap();
+        let target = collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection not found: {}", collection))?;
+
+        let mut scored: Vec<ScoredPoint> = target
+            .points
+            .iter()
+            .filter(|p| filter.matches(&p.payload))
+            .map(|p| ScoredPoint {
+                id: p.id.clone(),
+                score: dot(&vector, &p.vector),
+                payload: p.payload.clone(),
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(limit);
+        Ok(scored)
+    }
+
+    async fn search_groups(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        groups: usize,
+        per_group: usize,
+    ) -> Result<Vec<PointGroup>> {
+        let ranked = self.search(collection, vector, filter, usize::MAX).await?;
+
+        // Hits are sorted, so groups come out ordered by their best hit
+        let mut grouped: Vec<PointGroup> = Vec::new();
+        for hit in ranked {
+            match grouped
+                .iter()
+                .position(|g| g.filename == hit.payload.filename)
+            {
+                Some(i) if grouped[i].hits.len() < per_group => grouped[i].hits.push(hit),
+                Some(_) => {}
+                None if grouped.len() < groups => grouped.push(PointGroup {
+                    filename: hit.payload.filename.clone(),
+                    hits: vec![hit],
+                }),
+                None => {}
+            }
+        }
+        Ok(grouped)
+    }
+
+    async fn scroll(&self, collection: &str, filter: &PointFilter) -> Result<Vec<Record>> {
+        let collections = self.collections.read().unwrap();
+        let target = collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection not found: {}", collection))?;
+
+        Ok(target
+            .points
+            .iter()
+            .filter(|p| filter.matches(&p.payload))
+            .map(|p| Record {
+                id: p.id.clone(),
+                payload: p.payload.clone(),
+            })
+            .collect())
+    }
+
+    async fn retrieve(&self, collection: &str, ids: &[String]) -> Result<Vec<Record>> {
+        let collections = self.collections.read().unwrap();
+        let target = collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection not found: {}", collection))?;
+
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        Ok(target
+            .points
+            .iter()
+            .filter(|p| ids.contains(p.id.as_str()))
+            .map(|p| Record {
+                id: p.id.clone(),
+                payload: p.payload.clone(),
+            })
+            .collect())
+    }
+
+    async fn delete(&self, collection: &str, filter: &PointFilter) -> Result<()> {
+        let mut collections = self.collections.write().unwrap();
+        if let Some(target) = collections.get_mut(collection) {
+            let before = target.points.len();
+            target.points.retain(|p| !filter.matches(&p.payload));
+            if target.points.len() != before {
+                target.index_positions();
+                self.changed();
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let written = async {
+            let snapshot = bincode::serialize(&*self.collections.read().unwrap())?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // Write to a sibling file first so a crash never leaves a torn snapshot
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, snapshot).await?;
+            tokio::fs::rename(&tmp, path).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if written.is_err() {
+            self.changed();
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ChunkPayload;
+
+    fn point(id: &str, filename: &str, vector: Vec<f32>) -> StoredPoint {
+        StoredPoint {
+            id: id.to_string(),
+            vector,
+            payload: ChunkPayload {
+                filename: filename.to_string(),
+                text: format!("text of {}", id),
+                page: 1,
+                spans: vec![],
+                parent: None,
+                duplicates: vec![],
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn search_filters_and_ranks_by_dot_product() {
+        let store = MemoryStore::new();
+        store
+            .create_collection("test", 2, &CollectionMetadata::default())
+            .await
+            .unwrap();
+        store
+            .upsert(
+                "test",
+                vec![
+                    point("a", "one.pdf", vec![1.0, 0.0]),
+                    point("b", "one.pdf", vec![0.6, 0.8]),
+                    point("c", "two.pdf", vec![1.0, 0.0]),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let hits = store
+            .search("test", vec![0.0, 1.0], &PointFilter::filename("one.pdf"), 5)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+
+        // A batch with one bad vector is rejected whole
+        let batch = vec![point("d", "one.pdf", vec![1.0, 0.0]), point("e", "one.pdf", vec![1.0])];
+        assert!(store.upsert("test", batch).await.is_err());
+        let points = store.scroll("test", &PointFilter::all()).await.unwrap();
+        assert_eq!(points.len(), 3);
+
+        store
+            .delete("test", &PointFilter::filename("one.pdf"))
+            .await
+            .unwrap();
+        let remaining = store.scroll("test", &PointFilter::all()).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].payload.filename, "two.pdf");
+    }
+}
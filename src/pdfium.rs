@@ -0,0 +1,238 @@
+use anyhow::{Result, anyhow};
+use pdfium_render::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::chunk::Chunk;
+use crate::extract::{Backend, Extractor, File, Page};
+use crate::fuzzy;
+use crate::progress::{Progress, ProgressFn};
+
+static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+
+/// Highlight rectangle in PDF page coordinates, covering a run of chars on
+/// one line.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CharBbox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The process-wide pdfium binding, preferring a library next to the binary.
+/// Binding is only attempted once; without a library every call fails.
+pub fn get_pdfium() -> Result<&'static Pdfium> {
+    PDFIUM
+        .get_or_init(|| {
+            Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
+                .or_else(|_| Pdfium::bind_to_system_library())
+                .map(Pdfium::new)
+                .map_err(|e| format!("{:?}", e))
+        })
+        .as_ref()
+        .map_err(|e| anyhow!("Failed to bind to pdfium library: {}", e))
+}
+
+/// The chars `ch` stands for, each paired with `pdf_idx`. Ligatures are
+/// spelled out and invisible chars dropped.
+pub fn expand_ligatures(pdf_idx: usize, ch: char) -> Vec<(usize, char)> {
+    match ch {
+        '\u{00AD}' | '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{FEFF}' | '\u{2060}' => vec![],
+        '\u{FB00}' => vec![(pdf_idx, 'f'), (pdf_idx, 'f')],
+        '\u{FB01}' => vec![(pdf_idx, 'f'), (pdf_idx, 'i')],
+        '\u{FB02}' => vec![(pdf_idx, 'f'), (pdf_idx, 'l')],
+        '\u{FB03}' => vec![(pdf_idx, 'f'), (pdf_idx, 'f'), (pdf_idx, 'i')],
+        '\u{FB04}' => vec![(pdf_idx, 'f'), (pdf_idx, 'f'), (pdf_idx, 'l')],
+        '\u{FB05}' | '\u{FB06}' => vec![(pdf_idx, 's'), (pdf_idx, 't')],
+        _ => vec![(pdf_idx, ch)],
+    }
+}
+
+/// Text of a pdfium text page, with the index of the pdfium char every
+/// char of the text came from.
+pub fn page_chars(text_page: &PdfPageText) -> (String, Vec<usize>) {
+    let (indices, chars): (Vec<usize>, String) = text_page
+        .chars()
+        .iter()
+        .enumerate()
+        .flat_map(|(pdf_idx, c)| {
+            c.unicode_char()
+                .map(|ch| expand_ligatures(pdf_idx, ch))
+                .unwrap_or_default()
+        })
+        .unzip();
+    (chars, indices)
+}
+
+/// Extracts the text of every page with pdfium, recording where each char
+/// came from so chunks can be highlighted without searching for them.
+/// Pages whose text cannot be read are skipped.
+pub fn extract_pages(bytes: &[u8], on_progress: ProgressFn) -> Result<File> {
+    let doc = get_pdfium()?
+        .load_pdf_from_byte_slice(bytes, None)
+        .map_err(|e| anyhow!("PDFium load failed: {:?}", e))?;
+
+    let total = doc.pages().len() as usize;
+    let mut pages = Vec::with_capacity(total);
+    for (i, page) in doc.pages().iter().enumerate() {
+        match page.text() {
+            Ok(text_page) => {
+                let (content, char_indices) = page_chars(&text_page);
+                pages.push(Page {
+                    content,
+                    page_num: (i + 1) as u16,
+                    char_indices: Some(char_indices),
+                    backend: Some(Backend::Pdfium),
+                });
+            }
+            Err(e) => eprintln!("Failed to get text for page {}: {:?}", i + 1, e),
+        }
+        on_progress(Progress::PagesExtracted { done: i + 1, total });
+    }
+    Ok(File {
+        pages,
+        page_count: total as u16,
+    })
+}
+
+/// [`Extractor`] using pdfium; see [`extract_pages`].
+pub struct PdfiumExtractor;
+
+impl Extractor for PdfiumExtractor {
+    fn backend(&self) -> Backend {
+        Backend::Pdfium
+    }
+
+    fn extract(&self, bytes: &[u8], on_progress: ProgressFn) -> Result<File> {
+        extract_pages(bytes, on_progress)
+    }
+}
+
+pub fn extract_char_bboxes(
+    text_page: &PdfPageText,
+    pdf_char_indices: &[usize],
+) -> Result<Vec<CharBbox>> {
+    let chars = text_page.chars();
+    let mut result: Vec<CharBbox> = Vec::new();
+    let mut current: Option<CharBbox> = None;
+
+    for &idx in pdf_char_indices {
+        let ch = match chars.get(idx) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        if ch.unicode_char().is_some_and(|c| c.is_whitespace()) {
+            if let Some(r) = current.take() {
+                result.push(r);
+            }
+            continue;
+        }
+
+        let bounds = ch.loose_bounds()?;
+        let x = bounds.left().value;
+        let y = bounds.bottom().value;
+        let width = (bounds.right() - bounds.left()).value;
+        let height = (bounds.top() - bounds.bottom()).value;
+
+        if let Some(ref mut cur) = current {
+            if (cur.y - y).abs() < 2.0 {
+                // Same line: extend the rect rightward
+                cur.width = (x + width) - cur.x;
+                cur.height = cur.height.max(height);
+                continue;
+            }
+            result.push(current.take().unwrap());
+        }
+        current = Some(CharBbox {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    if let Some(r) = current {
+        result.push(r);
+    }
+
+    Ok(result)
+}
+
+/// Computes the highlight rectangles of every chunk span once, so search
+/// never has to open the PDF. Spans with known pdfium chars are exact; the
+/// others use their best fuzzy match on the page, and get no rectangles
+/// when there is none. Highlights are optional, so failures are logged and
+/// leave the affected spans without rectangles.
+pub fn precompute_highlights(bytes: &[u8], chunks: &mut [Chunk]) {
+    let loaded = get_pdfium().and_then(|pdfium| {
+        pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .map_err(|e| anyhow!("PDFium load failed: {:?}", e))
+    });
+    let doc = match loaded {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("{:?}, storing no highlights", e);
+            return;
+        }
+    };
+
+    // Visit every page once: page -> (chunk, span)
+    let mut by_page: BTreeMap<u16, Vec<(usize, usize)>> = BTreeMap::new();
+    for (c, chunk) in chunks.iter().enumerate() {
+        for (s, span) in chunk.spans.iter().enumerate() {
+            by_page.entry(span.page).or_default().push((c, s));
+        }
+    }
+
+    for (page_number, spans) in by_page {
+        let Some(page) = page_number
+            .checked_sub(1)
+            .and_then(|index| doc.pages().get(index).ok())
+        else {
+            eprintln!("Invalid page {} for PDF", page_number);
+            continue;
+        };
+        let Ok(text_page) = page.text() else {
+            eprintln!("Failed to get text for page {}", page_number);
+            continue;
+        };
+        let (text, indices) = page_chars(&text_page);
+        let char_entries: Vec<(usize, char)> = indices.into_iter().zip(text.chars()).collect();
+
+        for (c, s) in spans {
+            let chunk = &mut chunks[c];
+            let span = &chunk.spans[s];
+            let pdf_chars: Vec<usize> = match &span.pdf_chars {
+                Some(pdf_chars) => pdf_chars.clone().collect(),
+                None => {
+                    let needle: Vec<char> = chunk
+                        .content
+                        .chars()
+                        .skip(span.start)
+                        .take(span.end - span.start)
+                        .flat_map(char::to_lowercase)
+                        .collect();
+                    fuzzy::fuzzy_search(&char_entries, &needle, 0.85)
+                        .into_iter()
+                        .max_by(|a, b| a.2.total_cmp(&b.2))
+                        .map(|(start, end, _)| {
+                            char_entries[start..end]
+                                .iter()
+                                .map(|(idx, _)| *idx)
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                }
+            };
+            let rects = extract_char_bboxes(&text_page, &pdf_chars).unwrap_or_else(|e| {
+                eprintln!("Failed to get boxes on page {}: {}", page_number, e);
+                Vec::new()
+            });
+            chunk.spans[s].rects = Some(rects);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use serde::Serialize;
+
+/// Progress reported by the ingestion pipeline while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Progress {
+    PagesExtracted { done: usize, total: usize },
+    /// Re-extraction of the document with a fallback backend, for pages the
+    /// previous ones could not read.
+    PagesRetried { done: usize, total: usize },
+    ChunksCreated { chunks: usize },
+    EmbeddingBatch { batch: usize, batches: usize, embedded: usize },
+    PointsStored { stored: usize, total: usize },
+}
+
+/// Callback receiving [`Progress`] updates. Extraction reports from several
+/// threads at once, so implementations must be `Sync`.
+pub type ProgressFn<'a> = &'a (dyn Fn(Progress) + Send + Sync);
+
+/// A [`ProgressFn`] that discards every update.
+pub fn ignore(_: Progress) {}
@@ -1,110 +1,257 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use qdrant_client::Payload;
 use qdrant_client::Qdrant;
-use qdrant_client::QdrantError;
-use qdrant_client::qdrant::Distance;
-use qdrant_client::qdrant::SearchPointsBuilder;
-use qdrant_client::qdrant::SearchResponse;
-use qdrant_client::qdrant::UpsertPointsBuilder;
-use qdrant_client::qdrant::{Condition, CreateCollectionBuilder, Filter, VectorParamsBuilder};
-use qdrant_client::qdrant::{PointStruct, Value};
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::{
+    Condition, CreateCollectionBuilder, DeletePointsBuilder, Distance, Filter, GetPointsBuilder,
+    PointId, PointStruct, ScrollPointsBuilder, SearchPointGroupsBuilder, SearchPointsBuilder,
+    UpsertPointsBuilder, Value, VectorParamsBuilder,
+};
 use std::collections::HashMap;
 
-use crate::embed;
+use crate::store::{
+    ChunkPayload, CollectionMetadata, PointFilter, PointGroup, Record, ScoredPoint, StoredPoint,
+    VectorStore,
+};
 
-pub async fn setup_qdrant() -> Result<Qdrant, QdrantError> {
-    let client = Qdrant::from_url("http://localhost:6334").build()?;
-    client
-        .create_collection(CreateCollectionBuilder::new("repl").vectors_config(
-            VectorParamsBuilder::new(embed::get_dim() as u64, Distance::Dot),
-        ))
-        .await?;
+/// [`VectorStore`] backed by a Qdrant server.
+pub struct QdrantStore {
+    client: Qdrant,
+}
+
+impl QdrantStore {
+    pub fn new(client: Qdrant) -> Self {
+        Self { client }
+    }
 
-    Ok(client)
+    pub fn connect(url: &str) -> Result<Self> {
+        Ok(Self::new(Qdrant::from_url(url).build()?))
+    }
 }
 
-pub async fn init_collection(client: &Qdrant, collection_name: &str) -> Result<(), QdrantError> {
-    client
-        .create_collection(
-            CreateCollectionBuilder::new(collection_name).vectors_config(VectorParamsBuilder::new(
-                embed::get_dim() as u64,
-                Distance::Dot,
-            )),
-        )
-        .await?;
-    Ok(())
+fn to_filter(filter: &PointFilter) -> Option<Filter> {
+    if filter.filenames.is_empty() {
+        None
+    } else {
+        Some(Filter::must([Condition::matches(
+            "filename",
+            filter.filenames.clone(),
+        )]))
+    }
+}
+
+fn point_id_to_string(id: Option<PointId>) -> String {
+    match id.and_then(|id| id.point_id_options) {
+        Some(PointIdOptions::Uuid(uuid)) => uuid,
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        None => String::new(),
+    }
+}
+
+fn to_scored_point(point: qdrant_client::qdrant::ScoredPoint) -> Result<ScoredPoint> {
+    Ok(ScoredPoint {
+        id: point_id_to_string(point.id),
+        score: point.score,
+        payload: parse_payload(point.payload)?,
+    })
 }
 
-pub async fn store_embeddings(
-    client: &Qdrant,
-    collection_name: &str,
-    filename: &str,
-    embeddings: embed::Embeddings,
-) -> Result<String, QdrantError> {
-    assert_eq!(
-        embeddings.original.len(),
-        embeddings.embedded.len(),
-        "Original and embedded vectors must have the same length"
-    );
-
-    let unique_filename = format!("{}_{}", filename, uuid::Uuid::new_v4());
-
-    let points: Vec<PointStruct> = embeddings
-        .original
-        .into_iter()
-        .zip(embeddings.embedded)
-        .map(|(chunk, embedding)| {
-            let mut payload = HashMap::new();
-            payload.insert("filename".to_string(), Value::from(unique_filename.clone()));
-            payload.insert("text".to_string(), Value::from(chunk.content.clone()));
-            payload.insert("page".to_string(), Value::from(chunk.page as f32));
-            PointStruct::new(uuid::Uuid::new_v4().to_string(), embedding, payload)
-        })
-        .collect();
-
-    let response = client
-        .upsert_points(UpsertPointsBuilder::new(collection_name, points).wait(true))
-        .await?;
-    dbg!(response);
-    Ok(unique_filename)
+fn parse_payload(payload: HashMap<String, Value>) -> Result<ChunkPayload> {
+    let json = serde_json::Value::from(Payload::from(payload));
+    serde_json::from_value(json).map_err(|e| anyhow!("Invalid chunk payload: {}", e))
 }
 
-pub async fn run_query(
-    client: &Qdrant,
-    collection_name: &str,
-    filename: &str,
-    query: &str,
-) -> Result<SearchResponse, anyhow::Error> {
-    let emb_query = match embed::embed_query(query) {
-        Ok(embedding) => embedding,
-        Err(e) => {
-            eprintln!("Failed to embed query: {}", e);
-            return Err(e);
+#[async_trait]
+impl VectorStore for QdrantStore {
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dim: usize,
+        metadata: &CollectionMetadata,
+    ) -> Result<()> {
+        if self.client.collection_exists(collection).await? {
+            return Ok(());
+        }
+        let metadata: HashMap<String, serde_json::Value> =
+            serde_json::from_value(serde_json::to_value(metadata)?)?;
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(collection)
+                    .vectors_config(VectorParamsBuilder::new(dim as u64, Distance::Dot))
+                    .metadata(metadata),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn collection_metadata(&self, collection: &str) -> Result<Option<CollectionMetadata>> {
+        if !self.client.collection_exists(collection).await? {
+            return Ok(None);
+        }
+        let metadata = self
+            .client
+            .collection_info(collection)
+            .await?
+            .result
+            .and_then(|info| info.config)
+            .map(|config| config.metadata)
+            .unwrap_or_default();
+        if metadata.is_empty() {
+            return Ok(None);
+        }
+
+        let json = serde_json::Value::from(Payload::from(metadata));
+        Ok(Some(serde_json::from_value(json).map_err(|e| {
+            anyhow!("Invalid collection metadata: {}", e)
+        })?))
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        self.client.delete_collection(collection).await?;
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        let collections = self.client.list_collections().await?;
+        Ok(collections
+            .collections
+            .into_iter()
+            .map(|collection| collection.name)
+            .collect())
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<StoredPoint>) -> Result<()> {
+        let points = points
+            .into_iter()
+            .map(|point| {
+                let payload = Payload::try_from(serde_json::to_value(&point.payload)?)?;
+                Ok(PointStruct::new(point.id, point.vector, payload))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection, points).wait(true))
+            .await?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredPoint>> {
+        let mut request =
+            SearchPointsBuilder::new(collection, vector, limit as u64).with_payload(true);
+        if let Some(filter) = to_filter(filter) {
+            request = request.filter(filter);
         }
-    };
 
-    let filename_filter = Filter::must([Condition::matches("filename", filename.to_string())]);
+        let response = self.client.search_points(request).await?;
+
+        response.result.into_iter().map(to_scored_point).collect()
+    }
 
-    let search_result = client
-        .search_points(
-            SearchPointsBuilder::new(collection_name, emb_query, 5)
-                .filter(filename_filter)
-                .with_payload(true)
-                .build(),
+    async fn search_groups(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        groups: usize,
+        per_group: usize,
+    ) -> Result<Vec<PointGroup>> {
+        let mut request = SearchPointGroupsBuilder::new(
+            collection,
+            vector,
+            groups as u32,
+            "filename",
+            per_group as u32,
         )
-        .await?;
+        .with_payload(true);
+        if let Some(filter) = to_filter(filter) {
+            request = request.filter(filter);
+        }
 
-    Ok(search_result)
-}
+        let response = self.client.search_groups(request).await?;
 
-pub async fn delete_all_collections(client: &Qdrant) -> Result<(), Box<dyn std::error::Error>> {
-    // Get list of all collections
-    let collections = client.list_collections().await?;
+        response
+            .result
+            .map(|result| result.groups)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| {
+                let hits = group
+                    .hits
+                    .into_iter()
+                    .map(to_scored_point)
+                    .collect::<Result<Vec<_>>>()?;
+                let filename = hits
+                    .first()
+                    .map(|hit| hit.payload.filename.clone())
+                    .unwrap_or_default();
+                Ok(PointGroup { filename, hits })
+            })
+            .collect()
+    }
+
+    async fn scroll(&self, collection: &str, filter: &PointFilter) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        let mut offset: Option<PointId> = None;
+
+        loop {
+            let mut request = ScrollPointsBuilder::new(collection)
+                .limit(256)
+                .with_payload(true);
+            if let Some(filter) = to_filter(filter) {
+                request = request.filter(filter);
+            }
+            if let Some(offset) = offset.take() {
+                request = request.offset(offset);
+            }
+
+            let response = self.client.scroll(request).await?;
+            for point in response.result {
+                records.push(Record {
+                    id: point_id_to_string(point.id),
+                    payload: parse_payload(point.payload)?,
+                });
+            }
+
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
 
-    // Delete each collection
-    for collection in collections.collections {
-        println!("Deleting collection: {}", collection.name);
-        client.delete_collection(&collection.name).await?;
+        Ok(records)
     }
 
-    println!("All collections deleted!");
-    Ok(())
+    async fn retrieve(&self, collection: &str, ids: &[String]) -> Result<Vec<Record>> {
+        let ids: Vec<PointId> = ids.iter().map(|id| PointId::from(id.as_str())).collect();
+        let response = self
+            .client
+            .get_points(GetPointsBuilder::new(collection, ids).with_payload(true))
+            .await?;
+
+        response
+            .result
+            .into_iter()
+            .map(|point| {
+                Ok(Record {
+                    id: point_id_to_string(point.id),
+                    payload: parse_payload(point.payload)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete(&self, collection: &str, filter: &PointFilter) -> Result<()> {
+        let filter = to_filter(filter).unwrap_or_default();
+        self.client
+            .delete_points(DeletePointsBuilder::new(collection).points(filter).wait(true))
+            .await?;
+        Ok(())
+    }
 }
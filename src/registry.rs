@@ -0,0 +1,227 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chunk::{ChunkingConfig, FilterReport};
+use crate::extract::Backend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentStatus {
+    Processing,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    pub id: String,
+    /// Name of the file as uploaded.
+    pub filename: String,
+    /// Free-form labels used to scope library searches.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How the document is chunked, also when it is re-indexed. Documents
+    /// from before chunking was configurable used the default.
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+    /// Key the document's chunks are filtered by in the vector store.
+    /// Only known once the embeddings have been stored.
+    pub unique_filename: Option<String>,
+    pub content_hash: String,
+    pub status: DocumentStatus,
+    /// Why ingestion failed, when `status` is `Failed`.
+    #[serde(default)]
+    pub error: Option<String>,
+    pub pdf_path: PathBuf,
+    #[serde(default)]
+    pub pages: usize,
+    /// Number of chunks stored in the vector store.
+    #[serde(default)]
+    pub chunks: usize,
+    /// Number of chunks the filter dropped; see [`Registry::filter_report`].
+    #[serde(default)]
+    pub dropped_chunks: usize,
+    /// PDF library each page's text was extracted with.
+    #[serde(default)]
+    pub page_backends: BTreeMap<u16, Backend>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+/// Durable index of uploaded documents, kept as a JSON file next to the
+/// stored PDFs and filter reports.
+pub struct Registry {
+    dir: PathBuf,
+    documents: HashMap<String, DocumentRecord>,
+}
+
+impl Registry {
+    /// Opens the registry in `dir`, creating the directory if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("pdfs"))?;
+        fs::create_dir_all(dir.join("reports"))?;
+
+        let index = dir.join("registry.json");
+        let documents = if index.exists() {
+            let records: Vec<DocumentRecord> = serde_json::from_slice(&fs::read(&index)?)?;
+            records.into_iter().map(|r| (r.id.clone(), r)).collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self { dir, documents })
+    }
+
+    /// Writes the PDF bytes to disk and registers a new document in the
+    /// `Processing` state.
+    pub fn create(
+        &mut self,
+        id: &str,
+        filename: &str,
+        tags: Vec<String>,
+        chunking: ChunkingConfig,
+        bytes: &[u8],
+    ) -> Result<DocumentRecord> {
+        let pdf_path = self.dir.join("pdfs").join(format!("{}.pdf", id));
+        fs::write(&pdf_path, bytes)?;
+
+        let record = DocumentRecord {
+            id: id.to_string(),
+            filename: filename.to_string(),
+            tags,
+            chunking,
+            unique_filename: None,
+            content_hash: content_hash(bytes),
+            status: DocumentStatus::Processing,
+            error: None,
+            pdf_path,
+            pages: 0,
+            chunks: 0,
+            dropped_chunks: 0,
+            page_backends: BTreeMap::new(),
+            created_at: now(),
+        };
+
+        self.documents.insert(id.to_string(), record.clone());
+        self.persist()?;
+        Ok(record)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&DocumentRecord> {
+        self.documents.get(id)
+    }
+
+    /// All documents, oldest first.
+    pub fn list(&self) -> Vec<&DocumentRecord> {
+        let mut records: Vec<&DocumentRecord> = self.documents.values().collect();
+        records.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        records
+    }
+
+    /// Applies `f` to the record and persists the result.
+    pub fn update(&mut self, id: &str, f: impl FnOnce(&mut DocumentRecord)) -> Result<()> {
+        let record = self
+            .documents
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("No document with id: {}", id))?;
+        f(record);
+        self.persist()
+    }
+
+    /// Records the chunks dropped by the document's last ingest. Reports are
+    /// kept out of the index, which is rewritten on every update.
+    pub fn save_filter_report(&mut self, id: &str, report: &FilterReport) -> Result<()> {
+        fs::write(self.report_path(id), serde_json::to_vec(report)?)?;
+        self.update(id, |record| record.dropped_chunks = report.dropped.len())
+    }
+
+    /// The chunks dropped by the document's last ingest, if it got that far.
+    pub fn filter_report(&self, id: &str) -> Result<Option<FilterReport>> {
+        let path = self.report_path(id);
+        if !self.documents.contains_key(id) || !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    fn report_path(&self, id: &str) -> PathBuf {
+        self.dir.join("reports").join(format!("{}.json", id))
+    }
+
+    /// Removes the record, its stored PDF and its filter report.
+    pub fn remove(&mut self, id: &str) -> Result<Option<DocumentRecord>> {
+        let record = self.documents.remove(id);
+        if let Some(record) = &record {
+            if record.pdf_path.exists() {
+                fs::remove_file(&record.pdf_path)?;
+            }
+            let report = self.report_path(id);
+            if report.exists() {
+                fs::remove_file(report)?;
+            }
+            self.persist()?;
+        }
+        Ok(record)
+    }
+
+    fn persist(&self) -> Result<()> {
+        let index = self.dir.join("registry.json");
+        let tmp = index.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.list())?)?;
+        fs::rename(&tmp, &index)?;
+        Ok(())
+    }
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("vb-registry-{}", uuid::Uuid::new_v4()));
+
+        let mut registry = Registry::open(&dir).unwrap();
+        let record = registry
+            .create(
+                "doc-1",
+                "manual.pdf",
+                vec!["manuals".to_string()],
+                ChunkingConfig::default(),
+                b"%PDF-1.7",
+            )
+            .unwrap();
+        assert!(record.pdf_path.exists());
+        registry
+            .update("doc-1", |r| {
+                r.status = DocumentStatus::Ready;
+                r.unique_filename = Some("manual.pdf_1234".to_string());
+            })
+            .unwrap();
+
+        let reopened = Registry::open(&dir).unwrap();
+        let restored = reopened.get("doc-1").unwrap();
+        assert_eq!(restored.status, DocumentStatus::Ready);
+        assert_eq!(restored.unique_filename.as_deref(), Some("manual.pdf_1234"));
+        assert_eq!(restored.content_hash, content_hash(b"%PDF-1.7"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
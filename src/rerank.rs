@@ -0,0 +1,114 @@
+use anyhow::{Error, anyhow};
+use fastembed::{RerankInitOptions, TextRerank};
+use std::sync::{Arc, Mutex};
+
+pub use fastembed::RerankerModel;
+
+/// Scores (query, passage) pairs jointly, which is slower but more precise
+/// than comparing separately computed embeddings.
+pub trait Reranker: Send + Sync {
+    fn model_id(&self) -> &str;
+
+    /// Relevance of each passage to `query`, in input order. Higher is better.
+    fn score(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>, Error>;
+}
+
+/// [`Reranker`] running one of fastembed's cross-encoder models.
+pub struct FastembedReranker {
+    // `TextRerank::rerank` needs `&mut self`
+    model: Mutex<TextRerank>,
+    model_id: String,
+}
+
+impl FastembedReranker {
+    pub fn new(model: RerankerModel) -> Result<Self, Error> {
+        let model_id = TextRerank::get_model_info(&model).model_code;
+        Ok(Self {
+            model: Mutex::new(TextRerank::try_new(RerankInitOptions::new(model))?),
+            model_id,
+        })
+    }
+}
+
+impl Reranker for FastembedReranker {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn score(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>, Error> {
+        let results = self
+            .model
+            .lock()
+            .unwrap()
+            .rerank(query, passages, false, None)?;
+
+        let mut scores = vec![f32::NEG_INFINITY; passages.len()];
+        for result in results {
+            let slot = scores
+                .get_mut(result.index)
+                .ok_or_else(|| anyhow!("Reranker returned unknown index {}", result.index))?;
+            *slot = result.score;
+        }
+        Ok(scores)
+    }
+}
+
+/// Scores `items` against `query` on tokio's blocking pool and returns them
+/// best first, each with its reranker score.
+pub async fn rerank<T: Send + 'static>(
+    reranker: &Arc<dyn Reranker>,
+    query: &str,
+    items: Vec<T>,
+    text: impl Fn(&T) -> &str,
+) -> Result<Vec<(T, f32)>, Error> {
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let reranker = reranker.clone();
+    let query = query.to_string();
+    let passages: Vec<String> = items.iter().map(|item| text(item).to_string()).collect();
+    let scores = tokio::task::spawn_blocking(move || {
+        let passages: Vec<&str> = passages.iter().map(String::as_str).collect();
+        reranker.score(&query, &passages)
+    })
+    .await??;
+
+    let mut scored: Vec<(T, f32)> = items.into_iter().zip(scores).collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(scored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scores passages by how many query words they contain.
+    struct WordOverlap;
+
+    impl Reranker for WordOverlap {
+        fn model_id(&self) -> &str {
+            "word-overlap"
+        }
+
+        fn score(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>, Error> {
+            Ok(passages
+                .iter()
+                .map(|p| query.split_whitespace().filter(|w| p.contains(w)).count() as f32)
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn rerank_orders_items_by_reranker_score() {
+        let reranker: Arc<dyn Reranker> = Arc::new(WordOverlap);
+        let items = vec![(1, "the pump"), (2, "clean the filter monthly"), (3, "filter")];
+
+        let ranked = rerank(&reranker, "clean filter", items, |item| item.1)
+            .await
+            .unwrap();
+        let ids: Vec<i32> = ranked.iter().map(|(item, _)| item.0).collect();
+        assert_eq!(ids, vec![2, 3, 1]);
+        assert_eq!(ranked[0].1, 2.0);
+    }
+}
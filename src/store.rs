@@ -0,0 +1,768 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::chunk::{ChunkLocation, PageSpan, ParentChunk};
+use crate::embed::{self, Embedder};
+use crate::lexical::{self, FusedPoint, LexicalHit, LexicalIndex};
+use crate::progress::{self, Progress, ProgressFn};
+
+/// Payload stored alongside every chunk vector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkPayload {
+    pub filename: String,
+    pub text: String,
+    /// Page the chunk starts on.
+    pub page: u16,
+    /// See [`ChunkPayload::page_spans`].
+    #[serde(default)]
+    pub spans: Vec<PageSpan>,
+    /// Section the chunk belongs to, copied into every child so hits carry
+    /// their context without another lookup.
+    #[serde(default)]
+    pub parent: Option<ParentChunk>,
+    /// Where near-duplicates collapsed into this chunk were.
+    #[serde(default)]
+    pub duplicates: Vec<ChunkLocation>,
+}
+
+impl ChunkPayload {
+    /// Which characters of `text` come from which page. Chunks stored
+    /// without spans lie entirely on `page`.
+    pub fn page_spans(&self) -> Vec<PageSpan> {
+        if !self.spans.is_empty() {
+            return self.spans.clone();
+        }
+        vec![PageSpan {
+            page: self.page,
+            start: 0,
+            end: self.text.chars().count(),
+            pdf_chars: None,
+            rects: None,
+        }]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPoint {
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub payload: ChunkPayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: String,
+    pub payload: ChunkPayload,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScoredPoint {
+    pub id: String,
+    pub score: f32,
+    pub payload: ChunkPayload,
+}
+
+/// Best hits for one document, grouped by `filename`.
+#[derive(Debug, Clone)]
+pub struct PointGroup {
+    pub filename: String,
+    pub hits: Vec<ScoredPoint>,
+}
+
+/// Describes how the vectors in a collection were produced. Searching with
+/// a different model or prompts silently returns poor matches, so this is
+/// stored with the collection and checked on startup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionMetadata {
+    pub model_id: String,
+    pub query_prefix: String,
+    pub passage_prefix: String,
+}
+
+/// Restricts an operation to points whose `filename` is one of `filenames`.
+/// An empty filter matches every point in the collection.
+#[derive(Debug, Clone, Default)]
+pub struct PointFilter {
+    pub filenames: Vec<String>,
+}
+
+impl PointFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn filename(filename: &str) -> Self {
+        Self {
+            filenames: vec![filename.to_string()],
+        }
+    }
+
+    pub fn matches(&self, payload: &ChunkPayload) -> bool {
+        self.filenames.is_empty() || self.filenames.contains(&payload.filename)
+    }
+}
+
+/// Storage backend for chunk embeddings.
+///
+/// Vectors are compared by dot product in every implementation.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Creates the collection if it does not exist yet.
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dim: usize,
+        metadata: &CollectionMetadata,
+    ) -> Result<()>;
+
+    /// Metadata the collection was created with; `None` if the collection
+    /// does not exist or predates metadata.
+    async fn collection_metadata(&self, collection: &str) -> Result<Option<CollectionMetadata>>;
+
+    async fn delete_collection(&self, collection: &str) -> Result<()>;
+
+    async fn list_collections(&self) -> Result<Vec<String>>;
+
+    async fn upsert(&self, collection: &str, points: Vec<StoredPoint>) -> Result<()>;
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredPoint>>;
+
+    /// Returns up to `per_group` hits for each of the `groups` best matching
+    /// documents, best group first.
+    async fn search_groups(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: &PointFilter,
+        groups: usize,
+        per_group: usize,
+    ) -> Result<Vec<PointGroup>>;
+
+    /// Returns every point matching `filter`, without vectors.
+    async fn scroll(&self, collection: &str, filter: &PointFilter) -> Result<Vec<Record>>;
+
+    /// Returns the points with the given ids, without vectors. Ids not in
+    /// the collection are skipped.
+    async fn retrieve(&self, collection: &str, ids: &[String]) -> Result<Vec<Record>>;
+
+    async fn delete(&self, collection: &str, filter: &PointFilter) -> Result<()>;
+
+    /// Makes earlier changes durable, for stores that buffer them.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the collection, or checks that an existing one was built with the
+/// same model and prompts. Collections created before metadata was recorded
+/// are accepted as they are.
+pub async fn ensure_collection(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    dim: usize,
+    metadata: &CollectionMetadata,
+) -> Result<()> {
+    match store.collection_metadata(collection_name).await? {
+        Some(existing) if existing != *metadata => Err(anyhow::anyhow!(
+            "Collection {} was built with {:?} but the server is configured for {:?}; \
+             use the same embedding settings or start from an empty store",
+            collection_name,
+            existing,
+            metadata
+        )),
+        Some(_) => Ok(()),
+        None => store.create_collection(collection_name, dim, metadata).await,
+    }
+}
+
+const UPSERT_BATCH_SIZE: usize = 256;
+
+pub async fn store_embeddings(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    filename: &str,
+    embeddings: embed::Embeddings,
+) -> Result<String> {
+    store_embeddings_with_progress(store, collection_name, filename, embeddings, &progress::ignore)
+        .await
+}
+
+/// Like [`store_embeddings`], upserting in batches and reporting
+/// `PointsStored` after each one. Batches already stored are removed again
+/// if a later one fails.
+pub async fn store_embeddings_with_progress(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    filename: &str,
+    embeddings: embed::Embeddings,
+    on_progress: ProgressFn<'_>,
+) -> Result<String> {
+    assert_eq!(
+        embeddings.original.len(),
+        embeddings.embedded.len(),
+        "Original and embedded vectors must have the same length"
+    );
+
+    let unique_filename = format!("{}_{}", filename, uuid::Uuid::new_v4());
+
+    let points: Vec<StoredPoint> = embeddings
+        .original
+        .into_iter()
+        .zip(embeddings.embedded)
+        .map(|(chunk, embedding)| StoredPoint {
+            id: uuid::Uuid::new_v4().to_string(),
+            vector: embedding,
+            payload: ChunkPayload {
+                filename: unique_filename.clone(),
+                text: chunk.content,
+                page: chunk.page,
+                spans: chunk.spans,
+                parent: chunk.parent,
+                duplicates: chunk.duplicates,
+            },
+        })
+        .collect();
+
+    let total = points.len();
+    let mut stored = 0;
+    let mut points = points.into_iter().peekable();
+    while points.peek().is_some() {
+        let batch: Vec<StoredPoint> = points.by_ref().take(UPSERT_BATCH_SIZE).collect();
+        stored += batch.len();
+        if let Err(e) = store.upsert(collection_name, batch).await {
+            if let Err(e) = delete_document(store, collection_name, &unique_filename).await {
+                eprintln!("Failed to remove partly stored {}: {:?}", unique_filename, e);
+            }
+            return Err(e);
+        }
+        on_progress(Progress::PointsStored { stored, total });
+    }
+    store.flush().await?;
+
+    Ok(unique_filename)
+}
+
+/// Paging and filtering for [`run_query`].
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub limit: usize,
+    /// Number of top hits to skip.
+    pub offset: usize,
+    /// Drops vector hits scoring below this value. Keyword hits in
+    /// [`run_hybrid_query`] are kept.
+    pub min_score: Option<f32>,
+    /// Weight of vector against keyword hits in [`run_hybrid_query`], from
+    /// 0.0 to 1.0.
+    pub semantic_weight: f32,
+    /// Returns each parent section once, through its best matching child.
+    /// Chunks without a parent are returned as they are.
+    pub parents: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 5,
+            offset: 0,
+            min_score: None,
+            semantic_weight: 0.5,
+            parents: false,
+        }
+    }
+}
+
+pub async fn run_query(
+    store: &dyn VectorStore,
+    embedder: &Arc<dyn Embedder>,
+    collection_name: &str,
+    filename: &str,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<ScoredPoint>> {
+    let emb_query = match embed::embed_query_async(embedder, query).await {
+        Ok(embedding) => embedding,
+        Err(e) => {
+            eprintln!("Failed to embed query: {}", e);
+            return Err(e);
+        }
+    };
+
+    let hits = store
+        .search(
+            collection_name,
+            emb_query,
+            &PointFilter::filename(filename),
+            candidate_count(options),
+        )
+        .await?;
+    let hits = if options.parents {
+        best_per_parent(hits, |hit| &hit.payload)
+    } else {
+        hits
+    };
+
+    Ok(hits
+        .into_iter()
+        .skip(options.offset)
+        .take(options.limit)
+        .filter(|hit| options.min_score.is_none_or(|min| hit.score >= min))
+        .collect())
+}
+
+/// Hits fetched to fill a page of `options`. Collapsing children to their
+/// parents needs more, as siblings often match together.
+fn candidate_count(options: &SearchOptions) -> usize {
+    let wanted = options.offset.saturating_add(options.limit);
+    if options.parents { wanted.saturating_mul(4) } else { wanted }
+}
+
+/// Keeps the first, i.e. best, hit of every parent section.
+fn best_per_parent<T>(hits: Vec<T>, payload: impl Fn(&T) -> &ChunkPayload) -> Vec<T> {
+    let mut seen = std::collections::HashSet::new();
+    hits.into_iter()
+        .filter(|hit| match &payload(hit).parent {
+            Some(parent) => seen.insert(parent.id),
+            None => true,
+        })
+        .collect()
+}
+
+/// Fuses [`run_query`] with a keyword search over the same document using
+/// reciprocal rank fusion. A `semantic_weight` of 1.0 is a pure vector
+/// search and 0.0 a pure keyword search. `min_score` only applies to the
+/// vector hits: keyword hits are kept whatever their vector score, since
+/// exact identifiers often embed poorly, and come back with no
+/// `vector_score` when their vector hit was dropped.
+pub async fn run_hybrid_query(
+    store: &dyn VectorStore,
+    embedder: &Arc<dyn Embedder>,
+    keywords: &LexicalIndex,
+    collection_name: &str,
+    filename: &str,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<FusedPoint>> {
+    let semantic_weight = options.semantic_weight;
+    // Parents are collapsed once both lists are fused
+    let candidates = SearchOptions {
+        limit: candidate_count(options),
+        offset: 0,
+        parents: false,
+        ..*options
+    };
+
+    let semantic = if semantic_weight > 0.0 {
+        run_query(store, embedder, collection_name, filename, query, &candidates).await?
+    } else {
+        vec![]
+    };
+    let lexical = if semantic_weight < 1.0 {
+        let hits = keywords.search(filename, query, candidates.limit);
+        with_payloads(store, collection_name, hits).await?
+    } else {
+        vec![]
+    };
+
+    let fused = lexical::reciprocal_rank_fusion(semantic, lexical, semantic_weight);
+    let fused = if options.parents {
+        best_per_parent(fused, |hit| &hit.payload)
+    } else {
+        fused
+    };
+
+    Ok(fused
+        .into_iter()
+        .skip(options.offset)
+        .take(options.limit)
+        .collect())
+}
+
+/// Keyword hits with their payloads, in order. Hits whose point is gone from
+/// the store are dropped.
+async fn with_payloads(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    hits: Vec<LexicalHit>,
+) -> Result<Vec<ScoredPoint>> {
+    let ids: Vec<String> = hits.iter().map(|hit| hit.id.clone()).collect();
+    let mut payloads: HashMap<String, ChunkPayload> = store
+        .retrieve(collection_name, &ids)
+        .await?
+        .into_iter()
+        .map(|record| (record.id, record.payload))
+        .collect();
+
+    Ok(hits
+        .into_iter()
+        .filter_map(|hit| {
+            Some(ScoredPoint {
+                payload: payloads.remove(&hit.id)?,
+                id: hit.id,
+                score: hit.score,
+            })
+        })
+        .collect())
+}
+
+/// Builds the keyword index of a stored document from its points.
+pub async fn index_keywords(
+    store: &dyn VectorStore,
+    keywords: &LexicalIndex,
+    collection_name: &str,
+    unique_filename: &str,
+) -> Result<()> {
+    let points = store
+        .scroll(collection_name, &PointFilter::filename(unique_filename))
+        .await?;
+    keywords.index_document(unique_filename, &points).await
+}
+
+/// Searches across the given documents at once, grouping hits per document.
+pub async fn run_library_query(
+    store: &dyn VectorStore,
+    embedder: &Arc<dyn Embedder>,
+    collection_name: &str,
+    filenames: &[String],
+    query: &str,
+    groups: usize,
+    per_group: usize,
+) -> Result<Vec<PointGroup>> {
+    let emb_query = embed::embed_query_async(embedder, query).await?;
+    let filter = PointFilter {
+        filenames: filenames.to_vec(),
+    };
+
+    store
+        .search_groups(collection_name, emb_query, &filter, groups, per_group)
+        .await
+}
+
+/// Removes every chunk stored for the document with the given unique filename.
+pub async fn delete_document(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    unique_filename: &str,
+) -> Result<()> {
+    store
+        .delete(collection_name, &PointFilter::filename(unique_filename))
+        .await?;
+    store.flush().await
+}
+
+/// Deletes every collection in the store.
+pub async fn delete_all_collections(store: &dyn VectorStore) -> Result<()> {
+    for collection in store.list_collections().await? {
+        println!("Deleting collection: {}", collection);
+        store.delete_collection(&collection).await?;
+    }
+    store.flush().await?;
+
+    println!("All collections deleted!");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::embed::HashingEmbedder;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn collections_of_another_model_are_rejected() {
+        let store = MemoryStore::new();
+        let metadata = CollectionMetadata {
+            model_id: "intfloat/e5-small-v2".to_string(),
+            query_prefix: "query: ".to_string(),
+            passage_prefix: "passage: ".to_string(),
+        };
+        ensure_collection(&store, "test", 384, &metadata).await.unwrap();
+        ensure_collection(&store, "test", 384, &metadata).await.unwrap();
+
+        let other_model = CollectionMetadata {
+            model_id: "sentence-transformers/all-MiniLM-L6-v2".to_string(),
+            ..CollectionMetadata::default()
+        };
+        let error = ensure_collection(&store, "test", 384, &other_model)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("all-MiniLM-L6-v2"));
+        let other_prompts = CollectionMetadata {
+            query_prefix: String::new(),
+            ..metadata.clone()
+        };
+        assert!(ensure_collection(&store, "test", 384, &other_prompts).await.is_err());
+        assert_eq!(
+            store.collection_metadata("test").await.unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[tokio::test]
+    async fn stored_chunks_are_found_by_hybrid_query() {
+        let store = MemoryStore::new();
+        let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::new(64));
+        let keywords = LexicalIndex::new();
+        store
+            .create_collection("test", embedder.dim(), &CollectionMetadata::default())
+            .await
+            .unwrap();
+
+        let chunks = vec![
+            Chunk::on_page("Error E-4021 means the filter is clogged.", 3),
+            Chunk::on_page("The pump is rated for continuous use.", 7),
+        ];
+        let embeddings = embed::get_embeddings(embedder.as_ref(), chunks).unwrap();
+        let filename = store_embeddings(&store, "test", "manual.pdf", embeddings)
+            .await
+            .unwrap();
+        index_keywords(&store, &keywords, "test", &filename)
+            .await
+            .unwrap();
+
+        let hits = run_hybrid_query(
+            &store,
+            &embedder,
+            &keywords,
+            "test",
+            &filename,
+            "E-4021",
+            &SearchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(hits[0].payload.page, 3);
+        assert!(hits[0].vector_score.is_some() && hits[0].lexical_score.is_some());
+
+        // Above every vector score, so only the keyword hit is left
+        let options = SearchOptions {
+            min_score: Some(2.0),
+            ..SearchOptions::default()
+        };
+        let hits = run_hybrid_query(&store, &embedder, &keywords, "test", &filename, "E-4021", &options)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].payload.page, 3);
+        assert!(hits[0].vector_score.is_none() && hits[0].lexical_score.is_some());
+    }
+
+    #[tokio::test]
+    async fn parent_queries_return_each_section_once() {
+        let store = MemoryStore::new();
+        let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::new(64));
+        let keywords = LexicalIndex::new();
+        store
+            .create_collection("test", embedder.dim(), &CollectionMetadata::default())
+            .await
+            .unwrap();
+
+        let section = ParentChunk {
+            id: 0,
+            content: "Filter care. Clean the filter monthly. Replace a torn filter.".to_string(),
+            page: 2,
+            end_page: 2,
+        };
+        let chunks: Vec<Chunk> = ["Clean the filter monthly.", "Replace a torn filter."]
+            .into_iter()
+            .map(|text| Chunk {
+                parent: Some(section.clone()),
+                ..Chunk::on_page(text, 2)
+            })
+            .chain([Chunk::on_page("The filter housing is plastic.", 5)])
+            .collect();
+        let embeddings = embed::get_embeddings(embedder.as_ref(), chunks).unwrap();
+        let filename = store_embeddings(&store, "test", "manual.pdf", embeddings)
+            .await
+            .unwrap();
+        index_keywords(&store, &keywords, "test", &filename)
+            .await
+            .unwrap();
+
+        let options = SearchOptions {
+            parents: true,
+            ..SearchOptions::default()
+        };
+        let hits = run_hybrid_query(&store, &embedder, &keywords, "test", &filename, "filter", &options)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        let parents: Vec<Option<usize>> = hits
+            .iter()
+            .map(|hit| hit.payload.parent.as_ref().map(|parent| parent.id))
+            .collect();
+        assert!(parents.contains(&Some(0)) && parents.contains(&None));
+    }
+
+    #[tokio::test]
+    async fn library_queries_group_hits_by_selected_document() {
+        let store = MemoryStore::new();
+        let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::new(64));
+        store
+            .create_collection("test", embedder.dim(), &CollectionMetadata::default())
+            .await
+            .unwrap();
+
+        let documents: [(&str, &[&str]); 3] = [
+            (
+                "pump.pdf",
+                &[
+                    "Replace the filter cartridge.",
+                    "Rinse the filter cartridge.",
+                    "Replace the filter seal.",
+                ],
+            ),
+            (
+                "valve.pdf",
+                &["The valve has no filter cartridge.", "Tighten the valve nut."],
+            ),
+            ("notes.pdf", &["Replace the filter cartridge."]),
+        ];
+        let mut filenames = Vec::new();
+        for (name, texts) in documents {
+            let chunks = texts.iter().map(|text| Chunk::on_page(*text, 1)).collect();
+            let embeddings = embed::get_embeddings(embedder.as_ref(), chunks).unwrap();
+            filenames.push(store_embeddings(&store, "test", name, embeddings).await.unwrap());
+        }
+        // Leave notes.pdf out of the library
+        let (pump, valve) = (&filenames[0], &filenames[1]);
+        let selected = &filenames[..2];
+
+        let query = "replace the filter cartridge";
+        let groups = run_library_query(&store, &embedder, "test", selected, query, 10, 2)
+            .await
+            .unwrap();
+        let sizes: Vec<(&str, usize)> = groups
+            .iter()
+            .map(|group| (group.filename.as_str(), group.hits.len()))
+            .collect();
+        assert_eq!(sizes, [(pump.as_str(), 2), (valve.as_str(), 2)]);
+        assert!(groups[0].hits[0].score >= groups[1].hits[0].score);
+        assert!(groups.iter().all(|group| {
+            group.hits.iter().all(|hit| hit.payload.filename == group.filename)
+        }));
+
+        let groups = run_library_query(&store, &embedder, "test", selected, query, 1, 3)
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!((&groups[0].filename, groups[0].hits.len()), (pump, 3));
+    }
+
+    #[tokio::test]
+    async fn pages_do_not_overlap_and_min_score_drops_weak_hits() {
+        let store = MemoryStore::new();
+        let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::new(64));
+        let keywords = LexicalIndex::new();
+        store
+            .create_collection("test", embedder.dim(), &CollectionMetadata::default())
+            .await
+            .unwrap();
+
+        let chunks = [
+            "Clean the filter every month.",
+            "The filter sits behind the front panel.",
+            "A clogged filter raises error E-4021.",
+            "Order spare filters from the service desk.",
+            "The pump runs on 230 V.",
+            "Keep the manual near the pump.",
+        ]
+        .into_iter()
+        .zip(1..)
+        .map(|(text, page)| Chunk::on_page(text, page))
+        .collect();
+        let embeddings = embed::get_embeddings(embedder.as_ref(), chunks).unwrap();
+        let filename = store_embeddings(&store, "test", "manual.pdf", embeddings)
+            .await
+            .unwrap();
+        index_keywords(&store, &keywords, "test", &filename)
+            .await
+            .unwrap();
+
+        let page = |offset, limit| SearchOptions {
+            offset,
+            limit,
+            ..SearchOptions::default()
+        };
+        let query = "filter";
+        let all = run_query(&store, &embedder, "test", &filename, query, &page(0, 6))
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 6);
+        let mut paged = Vec::new();
+        for offset in [0, 2, 4] {
+            let hits = run_query(&store, &embedder, "test", &filename, query, &page(offset, 2))
+                .await
+                .unwrap();
+            paged.extend(hits.into_iter().map(|hit| hit.id));
+        }
+        let ids: Vec<String> = all.iter().map(|hit| hit.id.clone()).collect();
+        assert_eq!(paged, ids);
+
+        let all_fused =
+            run_hybrid_query(&store, &embedder, &keywords, "test", &filename, query, &page(0, 6))
+                .await
+                .unwrap();
+        let mut paged = Vec::new();
+        for offset in [0, 3] {
+            let hits = run_hybrid_query(
+                &store,
+                &embedder,
+                &keywords,
+                "test",
+                &filename,
+                query,
+                &page(offset, 3),
+            )
+            .await
+            .unwrap();
+            paged.extend(hits.into_iter().map(|hit| hit.id));
+        }
+        let ids: Vec<String> = all_fused.iter().map(|hit| hit.id.clone()).collect();
+        assert_eq!(paged, ids);
+
+        let min_score = all[2].score;
+        let options = SearchOptions {
+            min_score: Some(min_score),
+            ..page(0, 6)
+        };
+        let strong = run_query(&store, &embedder, "test", &filename, query, &options)
+            .await
+            .unwrap();
+        let expected = all.iter().filter(|hit| hit.score >= min_score).count();
+        // The threshold must keep some hits and drop others to test anything
+        assert!((3..6).contains(&expected));
+        assert_eq!(strong.len(), expected);
+        assert!(strong.iter().all(|hit| hit.score >= min_score));
+    }
+
+    #[tokio::test]
+    async fn failed_stores_leave_no_partial_document() {
+        let store = MemoryStore::new();
+        store
+            .create_collection("test", 2, &CollectionMetadata::default())
+            .await
+            .unwrap();
+
+        // The second batch fails on a vector of the wrong size
+        let count = UPSERT_BATCH_SIZE + 1;
+        let mut embedded = vec![vec![1.0, 0.0]; count];
+        embedded[count - 1] = vec![1.0];
+        let embeddings = embed::Embeddings {
+            original: (0..count).map(|_| Chunk::on_page("text", 1)).collect(),
+            embedded,
+        };
+        assert!(store_embeddings(&store, "test", "manual.pdf", embeddings).await.is_err());
+        let points = store.scroll("test", &PointFilter::all()).await.unwrap();
+        assert!(points.is_empty());
+    }
+}
@@ -150,9 +150,16 @@ export function renderHighlight(highlight, index, selectedHighlightIndex, pdfVie
     }
 }
 
+/**
+ * Renders a search hit's highlights, one per page it spans.
+ */
+export function renderHit(hit, index, selectedHighlightIndex, pdfViewer) {
+    hit.highlights.forEach(highlight => renderHighlight(highlight, index, selectedHighlightIndex, pdfViewer));
+}
+
 export function applyHighlights(results, pdfViewer) {
     clearAllHighlights();
-    results.forEach((h, i) => renderHighlight(h, i, -1, pdfViewer));
+    results.forEach((h, i) => renderHit(h, i, -1, pdfViewer));
 }
 
 /**
@@ -164,14 +171,16 @@ export function scrollToMatch(index, activeHighlights, pdfViewer) {
     if (!h) return index;
 
     clearAllHighlights();
-    renderHighlight(h, index, index, pdfViewer);
+    renderHit(h, index, index, pdfViewer);
 
-    const allRects = h.rects;
+    // Scroll to where the hit starts
+    const first    = h.highlights.find(highlight => highlight.rects.length > 0);
+    const allRects = first.rects;
     const maxY     = Math.max(...allRects.map(r => r.y + r.height));
     const padding  = 50;
 
     pdfViewer.scrollPageIntoView({
-        pageNumber: h.page,
+        pageNumber: first.page,
         destArray:  [null, { name: 'XYZ' }, null, maxY + padding, null],
     });
 
@@ -199,27 +208,94 @@ export async function getBackendResults(query, documentId, signal) {
         signal // 👈 fetch will throw AbortError if cancelled
     });
     if (!response.ok) throw new Error('Search failed');
-    return response.json();
+    const { hits } = await response.json();
+    // Hits that could not be located in the page have nothing to highlight
+    return hits.filter(hit => hit.highlights.some(highlight => highlight.rects.length > 0));
 }
 
 // ── Poll Backend ────────────────────────────────────────────────────────────
-export async function waitUntilReady(docId, intervalMs = 500, timeoutMs = 60000) {
-    const start = Date.now();
 
-    while (Date.now() - start < timeoutMs) {
+/**
+ * Human-readable progress line for a job returned by /api/jobs/{id}.
+ */
+export function describeJob(job) {
+    switch (job.stage) {
+        case 'queued':     return 'Waiting to start...';
+        case 'extracting': return job.pages_retried
+            ? `Retrying unreadable pages (${job.pages_retried}/${job.pages} pages)...`
+            : `Extracting text (${job.pages_extracted}/${job.pages} pages)...`;
+        case 'chunking':   return `Chunking ${job.pages} pages...`;
+        case 'embedding':  return `Embedding chunks (${job.chunks_embedded}/${job.chunks})...`;
+        case 'storing':    return `Storing chunks (${job.chunks_stored}/${job.chunks})...`;
+        case 'done':       return 'Ready';
+        case 'failed':     return `Indexing failed: ${job.error}`;
+        default:           return 'Processing...';
+    }
+}
+
+/**
+ * Polls the job until it is done. Calls onProgress(job) on every poll and
+ * throws with the server's error message if the job fails. The timeout only
+ * counts time without any visible progress.
+ */
+export async function waitUntilReady(docId, onProgress = () => {}, intervalMs = 500, timeoutMs = 60000) {
+    let lastChange = Date.now();
+    let lastSnapshot = null;
+
+    while (Date.now() - lastChange < timeoutMs) {
+        let job = null;
         try {
-            const res = await fetch(`/api/ready?id=${docId}`);
+            const res = await fetch(`/api/jobs/${docId}`);
             if (!res.ok) throw new Error(`HTTP ${res.status}`);
-
-            const data = await res.json();
-            if (data.ready) return;
+            job = await res.json();
         } catch (err) {
             console.error("Polling error:", err);
-            // Optional: decide if you want to break instead
+        }
+
+        if (job) {
+            onProgress(job);
+            if (job.stage === 'done') return job;
+            if (job.stage === 'failed') throw new Error(job.error || 'Indexing failed');
+
+            const snapshot = JSON.stringify(job);
+            if (snapshot !== lastSnapshot) {
+                lastSnapshot = snapshot;
+                lastChange = Date.now();
+            }
         }
 
         await new Promise(r => setTimeout(r, intervalMs));
     }
 
     throw new Error('Timed out waiting for PDF to be indexed');
-}
\ No newline at end of file
+}
+
+/**
+ * Follows the job over Server-Sent Events until it is done, calling
+ * onProgress(job) for every update. Falls back to polling if the event
+ * stream cannot be opened or drops before the job finishes.
+ */
+export function watchJob(docId, onProgress = () => {}) {
+    if (typeof EventSource === 'undefined') return waitUntilReady(docId, onProgress);
+
+    return new Promise((resolve, reject) => {
+        const source = new EventSource(`/api/jobs/${docId}/events`);
+
+        source.addEventListener('progress', (e) => {
+            const job = JSON.parse(e.data);
+            onProgress(job);
+            if (job.stage === 'done') {
+                source.close();
+                resolve(job);
+            } else if (job.stage === 'failed') {
+                source.close();
+                reject(new Error(job.error || 'Indexing failed'));
+            }
+        });
+
+        source.onerror = () => {
+            source.close();
+            waitUntilReady(docId, onProgress).then(resolve, reject);
+        };
+    });
+}
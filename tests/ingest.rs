@@ -0,0 +1,139 @@
+//! Upload to search without a Qdrant server or model files: a generated PDF
+//! goes through extraction, chunking, embedding and a persisted memory store.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, Stream, dictionary};
+use std::sync::Arc;
+use vb::chunk::{self, ChunkingConfig};
+use vb::embed::{self, Embedder, HashingEmbedder};
+use vb::extract::{Backend, FallbackExtractor};
+use vb::lexical::LexicalIndex;
+use vb::memory_store::MemoryStore;
+use vb::progress;
+use vb::store::{self, CollectionMetadata, SearchOptions};
+
+/// A PDF with one page per text.
+fn pdf(pages: &[&str]) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Courier",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let kids: Vec<Object> = pages
+        .iter()
+        .map(|text| {
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![50.into(), 700.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*text)]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            })
+            .into()
+        })
+        .collect();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[tokio::test]
+async fn uploaded_pdfs_are_searchable_after_a_restart() {
+    let path = std::env::temp_dir().join(format!("vb-ingest-{}.bin", uuid::Uuid::new_v4()));
+    let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::new(256));
+    let metadata = CollectionMetadata {
+        model_id: embedder.model_id().to_string(),
+        ..CollectionMetadata::default()
+    };
+    let bytes = pdf(&[
+        "Safety instructions for the pump and its power supply.",
+        "Replace the filter cartridge when error E-4021 is shown.",
+        "Warranty terms and service contacts.",
+    ]);
+
+    let filename = {
+        let store = MemoryStore::open(&path).unwrap();
+        store::ensure_collection(&store, "docs", embedder.dim(), &metadata)
+            .await
+            .unwrap();
+
+        // Falls back to lopdf where the pdfium library is missing
+        let pages = FallbackExtractor::new(&[Backend::Pdfium, Backend::Lopdf])
+            .extract(&bytes, &progress::ignore)
+            .unwrap();
+        assert_eq!(pages.len(), 3);
+        let (chunks, _) = chunk::chunk_pages(&pages, &ChunkingConfig::default(), None).unwrap();
+        let embeddings = embed::get_embeddings(embedder.as_ref(), chunks).unwrap();
+        store::store_embeddings(&store, "docs", "pump.pdf", embeddings)
+            .await
+            .unwrap()
+    };
+
+    let store = MemoryStore::open(&path).unwrap();
+    store::ensure_collection(&store, "docs", embedder.dim(), &metadata)
+        .await
+        .unwrap();
+    let hits = store::run_query(
+        &store,
+        &embedder,
+        "docs",
+        &filename,
+        "replace the filter cartridge",
+        &SearchOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits[0].payload.page, 2);
+
+    let keywords = LexicalIndex::new();
+    store::index_keywords(&store, &keywords, "docs", &filename)
+        .await
+        .unwrap();
+    let hits = store::run_hybrid_query(
+        &store,
+        &embedder,
+        &keywords,
+        "docs",
+        &filename,
+        "E-4021",
+        &SearchOptions {
+            semantic_weight: 0.0,
+            ..SearchOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits[0].payload.page, 2);
+
+    std::fs::remove_file(&path).unwrap();
+}